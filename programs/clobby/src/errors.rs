@@ -18,6 +18,48 @@ pub enum ClobbyProgramError {
     InvalidOrderId,
 
     #[msg("Invalid Event type, it can be either 0 or 1")]
-    InvalidEventType
+    InvalidEventType,
+
+    #[msg("Post only order would cross the opposing side")]
+    PostOnlyWouldCross,
+
+    #[msg("Market order would receive less than the quote limit")]
+    MarketOrderSlippageExceeded,
+
+    #[msg("Order expiry should be in the future")]
+    OrderAlreadyExpired,
+
+    #[msg("Order would match against an order of the same user")]
+    SelfTradeNotAllowed,
+
+    #[msg("Bookside does not have enough space for the orders")]
+    BookSideFull,
+
+    #[msg("Price should be in the multiples of the tick size")]
+    OffTickPrice,
+
+    #[msg("Order size is below the minimum base lots of the market")]
+    OrderSizeTooSmall,
+
+    #[msg("Order size is above the maximum base lots of the market")]
+    OrderSizeTooLarge,
+
+    #[msg("Order notional is below the minimum notional of the market")]
+    OrderNotionalTooSmall,
+
+    #[msg("Taker fee should cover the maker rebate, and the fee rates should not exceed 100%")]
+    InvalidFeeRates,
+
+    #[msg("User balance account still holds or has locked tokens")]
+    UserBalanceNotEmpty,
+
+    #[msg("User still has resting orders on the market")]
+    OpenOrdersExist,
+
+    #[msg("Only the market admin or the consume events authority can settle to the owner")]
+    UnauthorizedCranker,
+
+    #[msg("Token account is required to transfer the balance")]
+    MissingTokenAccount,
 
 }
\ No newline at end of file
@@ -0,0 +1,151 @@
+use anchor_lang::prelude::*;
+
+use crate::{errors::ClobbyProgramError, state::{BookSide, Market, Side, UserBalance}};
+
+pub fn amend_order(ctx:Context<AmendOrder>, args: AmendOrderArgs) -> Result<()> {
+
+    let accounts = ctx.accounts;
+
+    let market = &accounts.market;
+    market.validate_order_size(u64::from(args.base_lots), Some(args.quote_amount * u64::from(args.base_lots)))?;
+
+    let user_balance_account = &mut accounts.user_balance_account;
+    let mut bids = accounts.bids.load_mut()?;
+    let mut asks = accounts.asks.load_mut()?;
+
+    let (bookside, opposing_side): (&mut BookSide, &mut BookSide) = match args.side {
+        Side::Bid => (&mut bids, &mut asks),
+        Side::Ask => (&mut asks, &mut bids),
+    };
+
+    let order_count = bookside.order_count as usize;
+
+    let target_index = bookside.orders[..order_count]
+    .iter()
+    .position(|order| order.order_id == args.order_id)
+    .ok_or(ClobbyProgramError::InvalidOrderId)?;
+
+    let target_order = bookside.orders[target_index];
+
+    // Check only the order_authority can amend, through the sub account that placed the order !
+    require_keys_eq!(target_order.order_authority, accounts.user.key());
+    require_eq!(target_order.sub_account, args.sub_account);
+
+    let new_base_amount = u64::from(args.base_lots) * market.base_lot_size;
+    let new_price_in_ticks = market.quote_amount_to_ticks(args.quote_amount)?;
+
+    let (old_escrow, new_escrow) = match args.side {
+        Side::Bid => (
+            target_order.total_quote_amount(market),
+            market.bid_escrow(args.quote_amount, u64::from(args.base_lots)),
+        ),
+        Side::Ask => (target_order.base_amount, new_base_amount),
+    };
+
+    let keeps_priority = new_price_in_ticks == target_order.price_in_ticks && new_base_amount <= target_order.base_amount;
+
+    if keeps_priority {
+        bookside.orders[target_index].base_amount = new_base_amount;
+        msg!("Reduced the order {} in place", args.order_id);
+    }
+    else {
+        // the amended order only ever sits on the orderbook, same as a post only order.
+        // expired orders are skipped, as they would be removed instead of matched
+        let clock = Clock::get()?;
+        let opposing_count = opposing_side.order_count as usize;
+
+        let best_opposing_order = opposing_side.orders[..opposing_count]
+        .iter()
+        .find(|order| !order.is_expired(&clock));
+
+        if let Some(best_opposing_order) = best_opposing_order {
+            if args.side.crosses(new_price_in_ticks, best_opposing_order.price_in_ticks) {
+                return err!(ClobbyProgramError::PostOnlyWouldCross);
+            }
+        }
+
+        bookside.orders[target_index].remove(market.key());
+        bookside.compact_orders();
+
+        // the amended order goes behind the orders sitting at the same price
+        let index = bookside.order_count as usize;
+
+        bookside.orders[index] = target_order;
+        bookside.orders[index].base_amount = new_base_amount;
+        bookside.orders[index].price_in_ticks = new_price_in_ticks;
+
+        bookside.order_count += 1;
+        bookside.sort_orders_till_idx(index)?;
+
+        msg!("Moved the order {} to the new price", args.order_id);
+    }
+
+    // the escrow difference is netted against the user balance account,
+    // instead of transferring the tokens from or to the user
+    let balance_amount = match args.side {
+        Side::Bid => &mut user_balance_account.quote_amount,
+        Side::Ask => &mut user_balance_account.base_amount,
+    };
+
+    if new_escrow > old_escrow {
+        let escrow_to_add = new_escrow - old_escrow;
+        require_gte!(*balance_amount, escrow_to_add, ClobbyProgramError::InSufficientBalance);
+        *balance_amount -= escrow_to_add;
+    }
+    else {
+        *balance_amount += old_escrow - new_escrow;
+    }
+
+    // the new escrow is locked in place of the old one
+    match args.side {
+        Side::Bid => {
+            user_balance_account.quote_locked = user_balance_account.quote_locked - old_escrow + new_escrow;
+        },
+        Side::Ask => {
+            user_balance_account.base_locked = user_balance_account.base_locked - old_escrow + new_escrow;
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(args: AmendOrderArgs)]
+pub struct AmendOrder<'info>{
+
+    #[account(
+        mut,
+        signer,
+    )]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = user.key() == user_balance_account.user.key(),
+        constraint = user_balance_account.market.key() == market.key(),
+        seeds = [b"balance", market.key().as_ref(), user.key().as_ref(), &args.sub_account.to_le_bytes()],
+        bump,
+    )]
+    pub user_balance_account: Account<'info, UserBalance>,
+
+    #[account(
+        has_one = bids,
+        has_one = asks,
+    )]
+    pub market: Box<Account<'info, Market>>,
+
+    #[account(mut)]
+    pub bids: AccountLoader<'info, BookSide>,
+
+    #[account(mut)]
+    pub asks: AccountLoader<'info, BookSide>,
+}
+
+#[derive(AnchorDeserialize, AnchorSerialize)]
+pub struct AmendOrderArgs{
+    pub order_id: u64,
+    pub side: Side,
+    pub base_lots: u16, // New number of base lots
+    pub quote_amount: u64, // New price of each base lot, should be in the multiples of the tick size
+    pub sub_account: u16, // sub account of the user, that placed the order
+}
@@ -0,0 +1,115 @@
+use anchor_lang::prelude::*;
+
+use crate::{errors::ClobbyProgramError, state::{BookSide, EventParams, EventType, Market, MarketEvents, Side}};
+
+pub fn cancel_all_orders(ctx:Context<CancelAllOrders>, args: CancelAllOrdersArgs) -> Result<()> {
+
+    let accounts = ctx.accounts;
+
+    let mut market_events = accounts.market_events.load_mut()?;
+    let mut bids = accounts.bids.load_mut()?;
+    let mut asks = accounts.asks.load_mut()?;
+
+    let user = accounts.user.key();
+    let sub_account = args.sub_account;
+    let market = &accounts.market;
+
+    let sides_to_cancel: Vec<&mut BookSide> = match args.side {
+        Some(Side::Bid) => vec![&mut bids],
+        Some(Side::Ask) => vec![&mut asks],
+        None => vec![&mut bids, &mut asks],
+    };
+
+    let mut remaining_limit = args.limit.map_or(usize::MAX, usize::from);
+    let mut cancelled_count = 0;
+
+    for bookside in sides_to_cancel {
+
+        let side = bookside.get_side_in_enum()?;
+        let order_count = bookside.order_count as usize;
+
+        let orders_to_cancel = bookside.orders[..order_count]
+        .iter()
+        .filter(|order| order.order_authority == user && order.sub_account == sub_account)
+        .count()
+        .min(remaining_limit);
+
+        if orders_to_cancel == 0 {
+            continue;
+        }
+
+        if !market_events.can_add_event(cancelled_count + orders_to_cancel) {
+            return err!(ClobbyProgramError::EventsMaxLimit);
+        }
+
+        let mut side_cancelled_count = 0;
+
+        for order in bookside.orders[..order_count].iter_mut() {
+
+            if side_cancelled_count == orders_to_cancel {
+                break;
+            }
+
+            if order.order_authority != user || order.sub_account != sub_account {
+                continue;
+            }
+
+            market_events.add_event(EventParams {
+                order_id: order.order_id,
+                maker: order.order_authority,
+                maker_sub_account: order.sub_account,
+                side,
+                event_type: EventType::Out,
+                base_amount: order.base_amount,
+                quote_amount: order.total_quote_amount(market),
+            });
+
+            order.remove(market.key());
+            side_cancelled_count += 1;
+        }
+
+        bookside.compact_orders();
+
+        remaining_limit -= side_cancelled_count;
+        cancelled_count += side_cancelled_count;
+    }
+
+    msg!("successfully cancelled {} orders", cancelled_count);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct CancelAllOrders<'info>{
+
+    #[account(
+        mut,
+        signer,
+    )]
+    pub user: Signer<'info>,
+
+    #[account(
+        has_one = market_events,
+        has_one = bids,
+        has_one = asks,
+    )]
+    pub market: Box<Account<'info, Market>>,
+
+    #[account(
+        mut
+    )]
+    pub market_events: AccountLoader<'info, MarketEvents>,
+
+    #[account(mut)]
+    pub bids: AccountLoader<'info, BookSide>,
+
+    #[account(mut)]
+    pub asks: AccountLoader<'info, BookSide>,
+}
+
+#[derive(AnchorDeserialize, AnchorSerialize)]
+pub struct CancelAllOrdersArgs{
+    pub side: Option<Side>, // None -> cancel on both the sides
+    pub limit: Option<u16>, // Maximum number of orders to cancel
+    pub sub_account: u16, // only the orders of this sub account are cancelled
+}
@@ -1,33 +1,37 @@
 use anchor_lang::prelude::*;
 
-use crate::{errors::ClobbyProgramError, state::{BookSide, EventParams, Market, MarketEvents, Side}};
+use crate::{errors::ClobbyProgramError, state::{BookSide, BookSideOrder, EventParams, Market, MarketEvents, Side}};
 use crate::state::EventType;
 
 pub fn cancel_order(ctx:Context<CancelOrder>, args: CancelOrderArgs) -> Result<()>{
-    
-    let accounts= ctx.accounts;
+    cancel_bookside_order(ctx.accounts, args.side, |order| {
+        order.order_id == args.order_id && order.sub_account == args.sub_account
+    })
+}
 
+/// Cancels the first order on the given side, for which `is_target_order` returns true
+pub(crate) fn cancel_bookside_order<F>(accounts: &CancelOrder, side: Side, is_target_order: F) -> Result<()>
+where F: Fn(&BookSideOrder) -> bool
+{
     let bookside_account = &accounts.bookside_account;
     let mut bookside = bookside_account.load_mut()?;
     let mut market_event = accounts.market_events.load_mut()?;
 
-    let expected_bookside : Pubkey;
-
-    match args.side {
+    let expected_bookside = match side {
         Side::Bid => {
-            expected_bookside = accounts.market.bids.key();
+            accounts.market.bids.key()
         },
         Side::Ask => {
-            expected_bookside = accounts.market.asks.key();
+            accounts.market.asks.key()
         }
-    }
+    };
 
     require_keys_eq!(expected_bookside, bookside_account.key());
 
     let (target_index, target_order) = bookside.orders
     .iter_mut()
     .enumerate()
-    .find(|(_index, order)| order.order_id == args.order_id)
+    .find(|(_index, order)| is_target_order(order))
     .ok_or(ClobbyProgramError::InvalidOrderId)?;
 
     // Check only the order_authority can cancel !
@@ -44,17 +48,15 @@ pub fn cancel_order(ctx:Context<CancelOrder>, args: CancelOrderArgs) -> Result<(
             base_amount: target_order.base_amount,
             order_id: target_order.order_id,
             maker: target_order.order_authority,
-            quote_amount: target_order.quote_amount,
-            side: args.side,
+            maker_sub_account: target_order.sub_account,
+            quote_amount: target_order.total_quote_amount(&accounts.market),
+            side,
             event_type: EventType::Out,
         }
     );
 
     // reset the target order
-    target_order.base_amount = 0;
-    target_order.quote_amount = 0;
-    target_order.order_id = 0;
-    target_order.order_authority = accounts.market.key();
+    target_order.remove(accounts.market.key());
 
     /*
         Consider the bookside like
@@ -114,4 +116,5 @@ pub struct CancelOrder<'info>{
 pub struct CancelOrderArgs{
     pub order_id: u64,
     pub side: Side,
+    pub sub_account: u16, // sub account of the user, that placed the order
 }
\ No newline at end of file
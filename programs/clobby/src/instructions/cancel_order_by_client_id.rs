@@ -0,0 +1,24 @@
+use anchor_lang::prelude::*;
+
+use crate::state::Side;
+
+use super::{cancel_bookside_order, CancelOrder};
+
+/// Same as cancel_order, but the order is looked up by the client_order_id,
+/// which is only unique among the orders of the same user and sub account
+pub fn cancel_order_by_client_id(ctx:Context<CancelOrder>, args: CancelOrderByClientIdArgs) -> Result<()>{
+    let user = ctx.accounts.user.key();
+
+    cancel_bookside_order(ctx.accounts, args.side, |order| {
+        order.order_authority == user 
+        && order.sub_account == args.sub_account
+        && order.client_order_id == args.client_order_id
+    })
+}
+
+#[derive(AnchorDeserialize, AnchorSerialize)]
+pub struct CancelOrderByClientIdArgs{
+    pub client_order_id: u64,
+    pub side: Side,
+    pub sub_account: u16,
+}
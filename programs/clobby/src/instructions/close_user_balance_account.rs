@@ -0,0 +1,76 @@
+use anchor_lang::prelude::*;
+
+use crate::{errors::ClobbyProgramError, state::{BookSide, Market, UserBalance}};
+
+/// Closes the user balance account once the user is done with the market, and returns the rent.
+/// The balance should be settled, and the user should not have any resting orders on the market
+pub fn close_user_balance_account(ctx:Context<CloseUserBalanceAccount>, args: CloseUserBalanceAccountArgs) -> Result<()> {
+
+    let accounts = ctx.accounts;
+
+    let user = accounts.user.key();
+    let sub_account = args.sub_account;
+    let balance_account = &accounts.user_balance_account;
+
+    require!(
+        balance_account.base_amount == 0 && balance_account.quote_amount == 0, 
+        ClobbyProgramError::UserBalanceNotEmpty
+    );
+
+    let bids = accounts.bids.load()?;
+    let asks = accounts.asks.load()?;
+
+    for bookside in [&bids, &asks] {
+        let has_resting_orders = bookside.orders[..bookside.order_count as usize]
+        .iter()
+        .any(|order| order.order_authority == user && order.sub_account == sub_account);
+
+        require!(!has_resting_orders, ClobbyProgramError::OpenOrdersExist);
+    }
+
+    // the locked amounts of the removed orders are only released, once their events are consumed
+    require!(
+        balance_account.base_locked == 0 && balance_account.quote_locked == 0, 
+        ClobbyProgramError::UserBalanceNotEmpty
+    );
+
+    msg!("Closing the user balance account of {}", user);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(args: CloseUserBalanceAccountArgs)]
+pub struct CloseUserBalanceAccount<'info>{
+
+    #[account(
+        mut,
+        signer,
+    )]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        close = user,
+        constraint = user.key() == user_balance_account.user.key(),
+        constraint = user_balance_account.market.key() == market.key(),
+        seeds = [b"balance", market.key().as_ref(), user.key().as_ref(), &args.sub_account.to_le_bytes()],
+        bump,
+    )]
+    pub user_balance_account: Account<'info, UserBalance>,
+
+    #[account(
+        has_one = bids,
+        has_one = asks,
+    )]
+    pub market: Box<Account<'info, Market>>,
+
+    pub bids: AccountLoader<'info, BookSide>,
+
+    pub asks: AccountLoader<'info, BookSide>,
+}
+
+#[derive(AnchorDeserialize, AnchorSerialize)]
+pub struct CloseUserBalanceAccountArgs{
+    pub sub_account: u16,
+}
@@ -1,94 +1,121 @@
 use anchor_lang::prelude::*;
 
-use crate::state::{Market, MarketEvents, UserBalance, Side, EventType};
+use crate::state::{Event, Market, MarketEvents, UserBalance, Side, EventType};
 
 const MAX_EVENTS_TO_CONSUME:usize = 7;
 
-pub fn consume_events<'a, 'b, 'c, 'info>(ctx:Context<'a, 'b, 'c, 'info, ConsumeEvents<'info>>) -> Result<()> where 'c : 'info {
+pub fn consume_events<'a, 'b, 'c, 'info>(ctx:Context<'a, 'b, 'c, 'info, ConsumeEvents<'info>>) -> Result<Vec<Pubkey>> where 'c : 'info {
     let accounts = ctx.accounts;
+    let market = &mut accounts.market;
     // makers balance account should be passed here
     let remaining_accounts = ctx.remaining_accounts;
     let mut market_events = accounts.market_events.load_mut()?;
 
     let mut consumed_count: usize = 0;
 
-    for event in market_events.events.iter_mut(){
+    // the events are consumed in order, so the crank stops at the first event
+    // whose maker balance account is not passed
+    let mut blocked = false;
 
-        if event.id == 0 && event.order_id == 0 {
-            msg!("no events left to consume!");
-            break;
-        }
+    while let Some(event) = market_events.front() {
 
         if consumed_count == MAX_EVENTS_TO_CONSUME {
             msg!("maximum limit reached");
             break;
         }
 
-        let maker = event.maker;
-
-        let (balance_account, _bump) = Pubkey::find_program_address(
-            &[b"balance", maker.as_ref()], 
-            ctx.program_id,
-        );
+        let balance_account = maker_balance_address(&market.key(), &event, ctx.program_id);
 
         let maker_balance_acc = remaining_accounts
         .iter()
         .find(|account| *account.key == balance_account);
 
-        let mut consumed = false;
-
-        // else case will not occur most probably
-        if let Some(account_info) = maker_balance_acc {
-            let mut maker_balance_account = Account::<UserBalance>::try_from(account_info)?;
-
-            match event.get_event_in_enum()? {
-                EventType::Fill => {
-                    match event.get_side_in_enum()? {
-                        Side::Bid => {
-                            msg!("BASE BEFORE AMOUNT : {}", maker_balance_account.base_amount);
-                            maker_balance_account.base_amount += event.base_amount;
-                            msg!("BASE AFTER AMOUNT : {}", maker_balance_account.base_amount);
-                        },
-                        Side::Ask => {
-                            maker_balance_account.quote_amount += event.quote_amount;
-                        }
+        let Some(account_info) = maker_balance_acc else {
+            msg!("balance account {} of the event {} is missing", balance_account, event.id);
+            blocked = true;
+            break;
+        };
+
+        let mut maker_balance_account = Account::<UserBalance>::try_from(account_info)?;
+
+        match event.get_event_in_enum()? {
+            EventType::Fill => {
+                // the bids escrowed their maker fee, the asks pay it from the received quote amount.
+                // the rebates were already set aside from the taker fee, when the orders matched
+                let base_lots = event.base_amount / market.base_lot_size;
+                let quote_amount_per_lot = event.quote_amount.checked_div(base_lots).unwrap_or(0);
+                let maker_fee = market.maker_fee(quote_amount_per_lot, base_lots);
+                let maker_rebate = market.maker_rebate(quote_amount_per_lot, base_lots);
+
+                match event.get_side_in_enum()? {
+                    Side::Bid => {
+                        msg!("BASE BEFORE AMOUNT : {}", maker_balance_account.base_amount);
+                        maker_balance_account.base_amount += event.base_amount;
+                        msg!("BASE AFTER AMOUNT : {}", maker_balance_account.base_amount);
+                        maker_balance_account.quote_amount += maker_rebate;
+                        maker_balance_account.quote_locked -= event.quote_amount + maker_fee;
+                    },
+                    Side::Ask => {
+                        maker_balance_account.quote_amount += event.quote_amount - maker_fee + maker_rebate;
+                        maker_balance_account.base_locked -= event.base_amount;
                     }
-                },
-                EventType::Out => {
-                    match event.get_side_in_enum()? {
-                        Side::Bid => {
-                            maker_balance_account.quote_amount += event.quote_amount;
-                        },
-                        Side::Ask => {
-                            maker_balance_account.base_amount += event.base_amount;
-                        }
+                }
+
+                market.fees_accrued += maker_fee;
+            },
+            EventType::Out => {
+                match event.get_side_in_enum()? {
+                    Side::Bid => {
+                        maker_balance_account.quote_amount += event.quote_amount;
+                        maker_balance_account.quote_locked -= event.quote_amount;
+                    },
+                    Side::Ask => {
+                        maker_balance_account.base_amount += event.base_amount;
+                        maker_balance_account.base_locked -= event.base_amount;
                     }
                 }
             }
-
-            maker_balance_account.exit(ctx.program_id)?;
-            consumed = true;
-            consumed_count+=1;
         }
 
-        if consumed {
-            event.remove(accounts.market.key());
-        }   
-        
+        maker_balance_account.exit(ctx.program_id)?;
+
+        market_events.pop_front(market.key());
+        consumed_count+=1;
     }
 
-    if consumed_count > 0 {
-        for i in consumed_count..market_events.events_to_process as usize{
-            let j = i - consumed_count;
-            market_events.events.swap(i, j);
+    msg!("successfully consumed {} events", consumed_count);
+
+    // tell the cranker which balance accounts are missing for the next batch,
+    // so the blocked events can be consumed in the next call
+    let mut missing_accounts: Vec<Pubkey> = Vec::new();
+
+    if blocked {
+        for offset in 0..MAX_EVENTS_TO_CONSUME - consumed_count {
+            let Some(event) = market_events.get(offset) else {
+                break;
+            };
+
+            let balance_account = maker_balance_address(&market.key(), &event, ctx.program_id);
+
+            let is_passed = remaining_accounts.iter().any(|account| *account.key == balance_account);
+
+            if !is_passed && !missing_accounts.contains(&balance_account) {
+                missing_accounts.push(balance_account);
+            }
         }
-    
-        market_events.events_to_process -= consumed_count as u64;
     }
-    
-    msg!("successfully consumed {} events", consumed_count);
 
-    Ok(())
+    Ok(missing_accounts)
+}
+
+/// Balance account of the maker of the event
+fn maker_balance_address(market: &Pubkey, event: &Event, program_id: &Pubkey) -> Pubkey {
+    let (balance_account, _bump) = Pubkey::find_program_address(
+        &[b"balance", market.as_ref(), event.maker.as_ref(), &event.maker_sub_account.to_le_bytes()], 
+        program_id,
+    );
+
+    balance_account
 }
 
 
@@ -102,6 +129,7 @@ pub struct ConsumeEvents<'info>{
     )]
     pub consume_events_authority: Signer<'info>,
 
+    #[account(mut)]
     pub market: Account<'info, Market>,
 
     #[account(
@@ -1,12 +1,26 @@
 use anchor_lang::prelude::*;
 use anchor_spl::{associated_token::AssociatedToken, token_interface::{Mint, TokenAccount, TokenInterface}};
-use crate::state::Market;
+use crate::{errors::ClobbyProgramError, state::Market};
 
 
 /// Initialize the market account as well bids and asks
 pub fn create_market(ctx: Context<CreateMarket>, args:CreateMarketArgs) -> Result<()> {
 
     require_gt!(args.base_lot_size, 0);
+    require_gt!(args.quote_lot_size, 0);
+    require_gt!(args.tick_size, 0);
+    require_gt!(args.min_base_lots, 0);
+    if args.max_base_lots != 0 {
+        require_gte!(args.max_base_lots, args.min_base_lots);
+    }
+
+    // maker rebates are paid out of the taker fees
+    require!(
+        (0..=10_000).contains(&args.taker_fee_bps) && 
+        (-args.taker_fee_bps..=10_000).contains(&args.maker_fee_bps), 
+        ClobbyProgramError::InvalidFeeRates
+    );
+    require_gte!(10_000, args.referrer_share_bps, ClobbyProgramError::InvalidFeeRates);
 
     let accounts = ctx.accounts;
 
@@ -19,6 +33,16 @@ pub fn create_market(ctx: Context<CreateMarket>, args:CreateMarketArgs) -> Resul
     market.quote_token_vault = accounts.quote_token_vault.key();
     market.name = args.name;
     market.base_lot_size = args.base_lot_size;
+    market.quote_lot_size = args.quote_lot_size;
+    market.tick_size = args.tick_size;
+    market.min_base_lots = args.min_base_lots;
+    market.max_base_lots = args.max_base_lots;
+    market.min_notional = args.min_notional;
+    market.maker_fee_bps = args.maker_fee_bps;
+    market.taker_fee_bps = args.taker_fee_bps;
+    market.referrer_share_bps = args.referrer_share_bps;
+    market.fees_accrued = 0;
+    market.admin = accounts.signer.key();
     market.market_authority = accounts.market_authority.key();
     market.market_events = accounts.market_events.key();
     market.market_authority_bump = ctx.bumps.market_authority;
@@ -93,5 +117,13 @@ pub struct CreateMarket<'info> {
 pub struct CreateMarketArgs{
     pub name: String, 
     pub base_lot_size: u64,
+    pub quote_lot_size: u64,
+    pub tick_size: u64, // in quote lots
+    pub min_base_lots: u64,
+    pub max_base_lots: u64, // 0 -> no maximum
+    pub min_notional: u64, // in native quote tokens, 0 -> no minimum
+    pub maker_fee_bps: i16, // negative for rebates
+    pub taker_fee_bps: i16,
+    pub referrer_share_bps: u16, // share of the taker fee, after the maker rebates
     pub consume_events_authority: Pubkey,
 }
\ No newline at end of file
@@ -2,7 +2,7 @@ use anchor_lang::prelude::*;
 
 use crate::state::{Market, UserBalance};
 
-pub fn create_user_balance_account(ctx:Context<CreateUserBalanceAccount>) -> Result<()> {
+pub fn create_user_balance_account(ctx:Context<CreateUserBalanceAccount>, args: CreateUserBalanceAccountArgs) -> Result<()> {
 
     let accounts = ctx.accounts;
 
@@ -10,15 +10,19 @@ pub fn create_user_balance_account(ctx:Context<CreateUserBalanceAccount>) -> Res
 
     balance_account.user = accounts.user.key();
     balance_account.market = accounts.market.key();
+    balance_account.sub_account = args.sub_account;
     balance_account.base_token = accounts.market.base_token;
     balance_account.quote_token = accounts.market.quote_token;
     balance_account.base_amount = 0;
     balance_account.quote_amount = 0;
+    balance_account.base_locked = 0;
+    balance_account.quote_locked = 0;
 
     Ok(())
 }
 
 #[derive(Accounts)]
+#[instruction(args: CreateUserBalanceAccountArgs)]
 pub struct CreateUserBalanceAccount<'info>{
     
     #[account(
@@ -33,7 +37,7 @@ pub struct CreateUserBalanceAccount<'info>{
         init,
         space = 8 + UserBalance::INIT_SPACE,
         payer = user,
-        seeds = [b"balance", user.key().as_ref()],
+        seeds = [b"balance", market.key().as_ref(), user.key().as_ref(), &args.sub_account.to_le_bytes()],
         bump
     )]
 
@@ -41,4 +45,9 @@ pub struct CreateUserBalanceAccount<'info>{
 
     pub system_program: Program<'info, System>,
 
-}
\ No newline at end of file
+}
+
+#[derive(AnchorDeserialize, AnchorSerialize)]
+pub struct CreateUserBalanceAccountArgs{
+    pub sub_account: u16, // lets a user hold many balance accounts on the same market
+}
@@ -0,0 +1,107 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked};
+
+use crate::{errors::ClobbyProgramError, state::{Market, UserBalance}};
+
+/// Moves the tokens of the user into the market vaults, and credits them to the
+/// free balance of the user balance account, so they can fund the orders later
+pub fn deposit(ctx:Context<Deposit>, args: DepositArgs) -> Result<()> {
+
+    let accounts = ctx.accounts;
+
+    require!(accounts.user_base_token_account.amount >= args.base_amount, ClobbyProgramError::InSufficientBalance);
+    require!(accounts.user_quote_token_account.amount >= args.quote_amount, ClobbyProgramError::InSufficientBalance);
+
+    let cpi_program = accounts.token_program.to_account_info();
+
+    if args.base_amount > 0 {
+        let cpi_accounts = TransferChecked {
+            mint: accounts.base_token.to_account_info(),
+            from: accounts.user_base_token_account.to_account_info(),
+            to: accounts.base_token_vault.to_account_info(),
+            authority: accounts.user.to_account_info(),
+        };
+
+        let cpi_context = CpiContext::new(cpi_program.clone(), cpi_accounts);
+        transfer_checked(cpi_context, args.base_amount, accounts.base_token.decimals)?;
+    }
+
+    if args.quote_amount > 0 {
+        let cpi_accounts = TransferChecked {
+            mint: accounts.quote_token.to_account_info(),
+            from: accounts.user_quote_token_account.to_account_info(),
+            to: accounts.quote_token_vault.to_account_info(),
+            authority: accounts.user.to_account_info(),
+        };
+
+        let cpi_context = CpiContext::new(cpi_program, cpi_accounts);
+        transfer_checked(cpi_context, args.quote_amount, accounts.quote_token.decimals)?;
+    }
+
+    let user_balance_account = &mut accounts.user_balance_account;
+    user_balance_account.base_amount += args.base_amount;
+    user_balance_account.quote_amount += args.quote_amount;
+
+    msg!("Deposited {} base amount and {} quote amount", args.base_amount, args.quote_amount);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(args: DepositArgs)]
+pub struct Deposit<'info>{
+
+    #[account(
+        mut,
+        signer,
+    )]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = user.key() == user_balance_account.user.key(),
+        constraint = user_balance_account.market.key() == market.key(),
+        seeds = [b"balance", market.key().as_ref(), user.key().as_ref(), &args.sub_account.to_le_bytes()],
+        bump,
+    )]
+    pub user_balance_account: Account<'info, UserBalance>,
+
+    #[account(
+        has_one = base_token,
+        has_one = quote_token,
+        has_one = base_token_vault,
+        has_one = quote_token_vault,
+    )]
+    pub market: Box<Account<'info, Market>>,
+
+    #[account(
+        mut,
+        token::authority = user,
+        token::mint = base_token,
+    )]
+    pub user_base_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        token::authority = user,
+        token::mint = quote_token,
+    )]
+    pub user_quote_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub base_token_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub quote_token_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub base_token: Box<InterfaceAccount<'info, Mint>>,
+    pub quote_token: Box<InterfaceAccount<'info, Mint>>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(AnchorDeserialize, AnchorSerialize)]
+pub struct DepositArgs {
+    pub base_amount: u64, // native base tokens to deposit
+    pub quote_amount: u64, // native quote tokens to deposit
+    pub sub_account: u16,
+}
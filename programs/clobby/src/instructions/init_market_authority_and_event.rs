@@ -9,7 +9,8 @@ pub fn init_market_authority_and_event(ctx:Context<InitMarketAuthorityAndEvent>)
     let mut market_event = accounts.market_event.load_init()?;
 
     market_event.market = accounts.market.key();
-    market_event.events_to_process = 0;
+    market_event.head = 0;
+    market_event.count = 0;
     
 
     msg!("Initializing market authority and market event");
@@ -1,17 +1,39 @@
 pub use create_market::*;
 pub use create_bookside::*;
 pub use create_user_balance_account::*;
+pub use close_user_balance_account::*;
 pub use settle_user_balance::*;
+pub use settle_to_owner::*;
+pub use deposit::*;
+pub use withdraw::*;
 pub use init_market_authority_and_event::*;
 pub use place_order::*;
+pub use place_orders::*;
+pub use replace_quotes::*;
 pub use cancel_order::*;
+pub use cancel_order_by_client_id::*;
+pub use cancel_all_orders::*;
+pub use amend_order::*;
 pub use consume_events::*;
+pub use prune_expired_orders::*;
+pub use sweep_fees::*;
 
 mod create_market;
 mod create_bookside;
 mod create_user_balance_account;
+mod close_user_balance_account;
 mod settle_user_balance;
+mod settle_to_owner;
+mod deposit;
+mod withdraw;
 mod init_market_authority_and_event;
 mod place_order;
+mod place_orders;
+mod replace_quotes;
 mod cancel_order;
-mod consume_events;
\ No newline at end of file
+mod cancel_order_by_client_id;
+mod cancel_all_orders;
+mod amend_order;
+mod consume_events;
+mod prune_expired_orders;
+mod sweep_fees;
\ No newline at end of file
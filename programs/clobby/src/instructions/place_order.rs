@@ -4,11 +4,22 @@ use anchor_spl::{associated_token::{get_associated_token_address_with_program_id
 use crate::{errors::ClobbyProgramError, state::{BookSide, BookSideOrder, EventParams, EventType, Market, MarketEvents, Side, UserBalance}};
 
 const MAX_ORDERS_TO_MATCH:usize = 5;
+const MAX_ORDERS_TO_REMOVE:usize = 10; // expired or self trade cancelled orders, on top of the matched ones
+
+struct DeleteOrders {
+    pub index: usize, // index of the order in the opposing bookside
+    pub order_id: u64,
+    pub total_quote_amount: u64,
+    pub event_type: EventType,
+}
 
 struct EditOrders {
+    pub index: usize, // index of the order in the opposing bookside
     pub order_id : u64,
     pub base_amount_to_set: u64,
-    pub total_quote_amount: u64
+    pub base_amount_eaten: u64,
+    pub total_quote_amount: u64,
+    pub event_type: EventType,
 }
 
 pub fn place_order(ctx:Context<PlaceOrder>, args:PlaceOrderArgs) -> Result<()> {
@@ -21,9 +32,21 @@ pub fn place_order(ctx:Context<PlaceOrder>, args:PlaceOrderArgs) -> Result<()> {
     let mut asks = accounts.asks.load_mut()?;
     let mut bids = accounts.bids.load_mut()?;
 
-    let mut orders_to_delete : Vec<(u64, u64)> = Vec::new();
+    let mut orders_to_delete : Vec<DeleteOrders> = Vec::new();
     let mut orders_to_edit: Vec<EditOrders> = Vec::new();
     let mut orders_matched = 0_usize;
+    // orders removed without being matched, i.e expired or self trade cancelled orders
+    let mut orders_removed = 0_usize;
+
+    let clock = Clock::get()?;
+
+    let (expires_at_timestamp, expires_at_slot) = OrderExpiry::resolve(args.expiry, &clock)?;
+
+    let notional = match args.order_type {
+        OrderType::Market { .. } => None,
+        _ => Some(args.quote_amount * u64::from(args.base_lots)),
+    };
+    market.validate_order_size(u64::from(args.base_lots), notional)?;
 
     let base_amount:u64 = u64::from(args.base_lots) * market.base_lot_size;
 
@@ -31,20 +54,52 @@ pub fn place_order(ctx:Context<PlaceOrder>, args:PlaceOrderArgs) -> Result<()> {
     
     let mut limit = MAX_ORDERS_TO_MATCH;
 
+    // market orders have no limit price, they are bounded by the quote_limit instead
+    let limit_price_in_ticks = match (args.order_type, args.side) {
+        (OrderType::Market { .. }, Side::Bid) => u64::MAX,
+        (OrderType::Market { .. }, Side::Ask) => 0,
+        _ => market.quote_amount_to_ticks(args.quote_amount)?,
+    };
+
+    // quote amount left to spend by a market bid
+    let mut remaining_quote_budget = match args.order_type {
+        OrderType::Market { quote_limit } => quote_limit,
+        _ => 0,
+    };
+
+    // base amount dropped without being matched or placed on the orderbook
+    let mut unfilled_base_amount = 0_u64;
+    let mut cancel_taker_remaining = false;
+
+    // base and quote amount matched against the opposing orders
+    let mut total_base_matched = 0_u64;
+    let mut total_quote_matched = 0_u64;
+    // rebates owed to the makers for the fills, paid out of the taker fee
+    let mut total_maker_rebates = 0_u64;
+
     let opposing_side:&mut BookSide;
     let taker_side:&mut BookSide;
-    
-    let transfer_token_amount: u64;
 
+    // free balance of the token to trade, that can fund the escrow of the order
+    let free_balance = match (args.fund_from_balance, args.side) {
+        (false, _) => 0,
+        (true, Side::Bid) => user_balance_account.quote_amount,
+        (true, Side::Ask) => user_balance_account.base_amount,
+    };
+    
     match args.side {
         Side::Bid =>{
 
             // on the bidding side, quote token is used to trade
             require_keys_eq!(accounts.token_to_trade.key(), market.quote_token);
-            require!(accounts.user_token_account.amount >= args.quote_amount * args.base_lots as u64, ClobbyProgramError::InSufficientBalance);
 
+            let quote_to_escrow = match args.order_type {
+                OrderType::Market { quote_limit } => quote_limit,
+                _ => args.quote_amount * args.base_lots as u64,
+            };
+
+            require!(accounts.user_token_account.amount + free_balance >= quote_to_escrow, ClobbyProgramError::InSufficientBalance);
 
-            transfer_token_amount = args.quote_amount * u64::from(args.base_lots);
             taker_side = &mut bids;
             opposing_side = &mut asks;
 
@@ -53,9 +108,8 @@ pub fn place_order(ctx:Context<PlaceOrder>, args:PlaceOrderArgs) -> Result<()> {
 
             // on the asking side, base token is used to trade
             require_keys_eq!(accounts.token_to_trade.key(), market.base_token);
-            require!(accounts.user_token_account.amount >= base_amount * args.base_lots as u64, ClobbyProgramError::InSufficientBalance);
+            require!(accounts.user_token_account.amount + free_balance >= base_amount, ClobbyProgramError::InSufficientBalance);
 
-            transfer_token_amount = base_amount;
             taker_side = &mut asks;
             opposing_side = &mut bids;
         }
@@ -64,12 +118,12 @@ pub fn place_order(ctx:Context<PlaceOrder>, args:PlaceOrderArgs) -> Result<()> {
     let expected_token_vault = get_associated_token_address_with_program_id(
         &market.market_authority,
         &accounts.token_to_trade.key(),
-        &*accounts.token_program.key,
+        accounts.token_program.key,
     );
 
     require_keys_eq!(expected_token_vault.key(), accounts.token_vault.key());
 
-    for opposing_order in opposing_side.orders.iter(){
+    for (index, opposing_order) in opposing_side.orders.iter().enumerate(){
 
         if opposing_order.order_id == 0 {
             msg!("No Orders to Match further");
@@ -81,30 +135,150 @@ pub fn place_order(ctx:Context<PlaceOrder>, args:PlaceOrderArgs) -> Result<()> {
             break;
         }
 
+        if orders_removed == MAX_ORDERS_TO_REMOVE {
+            msg!("Max orders to remove reached!");
+            break;
+        }
+
         if remaining_order_amount == 0 {
             msg!("remaining order amount becomes zero");
             break;
         }
 
-        if opposing_order.quote_amount > args.quote_amount {
-            msg!("Opposing Quote Amount becomes higher !");
+        // expired orders are removed, and the maker gets back the escrow through an out event
+        if opposing_order.is_expired(&clock) {
+            msg!("Removing the expired order {}", opposing_order.order_id);
+
+            orders_to_delete.push(DeleteOrders {
+                index,
+                order_id: opposing_order.order_id,
+                total_quote_amount: opposing_order.total_quote_amount(market),
+                event_type: EventType::Out,
+            });
+
+            orders_removed += 1;
+            continue;
+        }
+
+        if !args.side.crosses(limit_price_in_ticks, opposing_order.price_in_ticks) {
+            msg!("Opposing order does not cross the limit price !");
             break;
         }
 
-        let base_amount_eaten = opposing_order.base_amount.min(remaining_order_amount);
+        // a post only order should only ever rest on the book as a maker
+        if args.order_type == OrderType::PostOnly {
+            return err!(ClobbyProgramError::PostOnlyWouldCross);
+        }
+
+        if opposing_order.order_authority == accounts.user.key() {
+            match args.self_trade_behavior {
+                SelfTradeBehavior::Allow => {},
+                SelfTradeBehavior::AbortTransaction => {
+                    return err!(ClobbyProgramError::SelfTradeNotAllowed);
+                },
+                SelfTradeBehavior::CancelMaker => {
+                    msg!("Cancelling the self trading maker order {}", opposing_order.order_id);
+
+                    orders_to_delete.push(DeleteOrders {
+                        index,
+                        order_id: opposing_order.order_id,
+                        total_quote_amount: opposing_order.total_quote_amount(market),
+                        event_type: EventType::Out,
+                    });
+
+                    orders_removed += 1;
+                    continue;
+                },
+                SelfTradeBehavior::CancelTaker => {
+                    msg!("Cancelling the remaining of the self trading taker order");
+                    cancel_taker_remaining = true;
+                    break;
+                },
+                SelfTradeBehavior::DecrementAndCancel => {
+                    // both the orders are decremented by the overlapping amount without trading,
+                    // the maker gets back the escrow of the decremented amount through an out event
+                    let base_amount_decremented = opposing_order.base_amount.min(remaining_order_amount);
+                    let total_quote_amount = market.bid_escrow(opposing_order.quote_amount(market), base_amount_decremented / market.base_lot_size);
+
+                    msg!("Decrementing the self trading orders by {} base amount", base_amount_decremented);
+
+                    if base_amount_decremented == opposing_order.base_amount {
+                        orders_to_delete.push(DeleteOrders {
+                            index,
+                            order_id: opposing_order.order_id,
+                            total_quote_amount,
+                            event_type: EventType::Out,
+                        });
+                    }
+                    else{
+                        let base_amount_to_set = opposing_order.base_amount - base_amount_decremented;
+                        orders_to_edit.push(EditOrders { 
+                            index,
+                            order_id: opposing_order.order_id, 
+                            base_amount_to_set, 
+                            base_amount_eaten: base_amount_decremented, 
+                            total_quote_amount,
+                            event_type: EventType::Out,
+                        });
+                    }
+
+                    // the decremented amount of the taker is never escrowed
+                    remaining_order_amount -= base_amount_decremented;
+                    unfilled_base_amount += base_amount_decremented;
+                    orders_removed += 1;
+                    continue;
+                }
+            }
+        }
+
+        let mut base_amount_eaten = opposing_order.base_amount.min(remaining_order_amount);
 
-        // quote amount used to buy or sell one base lot
-        let quote_amount_at = opposing_order.quote_amount.min(args.quote_amount);
+        // quote amount used to buy or sell one base lot, the maker's price is always used
+        // so the taker gets any price improvement over its limit price
+        let quote_amount_at = opposing_order.quote_amount(market);
+
+        let mut eaten_base_lots = base_amount_eaten / market.base_lot_size;
+        let mut quote_budget_exhausted = false;
+
+        // a market bid can only eat as many base lots as its remaining quote budget affords
+        if matches!(args.order_type, OrderType::Market { .. }) && args.side == Side::Bid {
+            // a zero priced order is always affordable
+            let affordable_base_lots = remaining_quote_budget
+            .checked_div(quote_amount_at)
+            .unwrap_or(eaten_base_lots);
+
+            quote_budget_exhausted = affordable_base_lots < eaten_base_lots;
+            eaten_base_lots = eaten_base_lots.min(affordable_base_lots);
+
+            if eaten_base_lots == 0 {
+                msg!("Quote budget of the market order is exhausted");
+                break;
+            }
+
+            base_amount_eaten = eaten_base_lots * market.base_lot_size;
+            remaining_quote_budget -= quote_amount_at * eaten_base_lots;
+        }
 
-        let eaten_base_lots = base_amount_eaten / market.base_lot_size;
         let total_quote_amount = quote_amount_at * eaten_base_lots;
 
         if base_amount_eaten == opposing_order.base_amount {
-            orders_to_delete.push((opposing_order.order_id, total_quote_amount));
+            orders_to_delete.push(DeleteOrders {
+                index,
+                order_id: opposing_order.order_id,
+                total_quote_amount,
+                event_type: EventType::Fill,
+            });
         }
         else{
             let base_amount_to_set = opposing_order.base_amount - base_amount_eaten;
-            orders_to_edit.push(EditOrders { order_id: opposing_order.order_id, base_amount_to_set,  total_quote_amount});
+            orders_to_edit.push(EditOrders { 
+                index,
+                order_id: opposing_order.order_id, 
+                base_amount_to_set, 
+                base_amount_eaten, 
+                total_quote_amount,
+                event_type: EventType::Fill,
+            });
         }
 
         match taker_side.get_side_in_enum()? {
@@ -120,17 +294,91 @@ pub fn place_order(ctx:Context<PlaceOrder>, args:PlaceOrderArgs) -> Result<()> {
         }
 
         remaining_order_amount -= base_amount_eaten;
+        total_base_matched += base_amount_eaten;
+        total_quote_matched += total_quote_amount;
+        total_maker_rebates += market.maker_rebate(quote_amount_at, eaten_base_lots);
         limit -= 1;
         orders_matched += 1;
+
+        // nothing is matched or removed after a partially filled order, 
+        // the orders behind it stay untouched on the orderbook
+        if quote_budget_exhausted {
+            msg!("Quote budget of the market order is exhausted");
+            break;
+        }
     }
 
-    // if the order is IOC and remaing amount > 0, through partially filled error 1
-    if args.ioc && remaining_order_amount > 0 {
+    // if the order is FOK and not matched completely, through partially filled error 1.
+    // the amount decremented by a self trade is never filled, so it does not count as matched
+    if args.order_type == OrderType::FillOrKill && total_base_matched < base_amount {
         return err!(ClobbyProgramError::OrderFilledPartially);
     }
 
+    // an IOC order never sits on the orderbook, the unfilled remaining is simply dropped
+    let cancel_remaining = args.order_type == OrderType::ImmediateOrCancel || cancel_taker_remaining;
+
+    if cancel_remaining && remaining_order_amount > 0 {
+        msg!("Cancelling the unfilled {} base amount of the order", remaining_order_amount);
+        unfilled_base_amount += remaining_order_amount;
+        remaining_order_amount = 0;
+    }
+
+    // the taker fee is charged on top of the matched quote amount for bids, 
+    // and deducted from the received quote amount for asks
+    let taker_fee = market.taker_fee(total_quote_matched);
+
+    if args.side == Side::Ask {
+        user_balance_account.quote_amount -= taker_fee;
+    }
+
+    // the maker rebates are credited to the makers, when the fill events are consumed
+    let mut protocol_fee = taker_fee - total_maker_rebates;
+    msg!("charged {} quote amount as taker fee", taker_fee);
+
+    if let Some(referrer_balance_account) = accounts.referrer_balance_account.as_mut() {
+        let referrer_fee = market.referrer_share(protocol_fee);
+        referrer_balance_account.quote_amount += referrer_fee;
+        protocol_fee -= referrer_fee;
+        msg!("credited {} quote amount to the referrer", referrer_fee);
+    }
+
+    market.fees_accrued += protocol_fee;
+
+    // a market order never sits on the orderbook either, its unspent escrow is refunded 
+    // to the user balance account, as the whole escrow gets transferred below
+    if let OrderType::Market { quote_limit } = args.order_type {
+        match args.side {
+            Side::Bid => {
+                user_balance_account.quote_amount += remaining_quote_budget;
+                msg!("refunded {} unspent quote amount of the market order", remaining_quote_budget);
+            },
+            Side::Ask => {
+                // for asks, quote_limit is the minimum quote amount to receive after the fee
+                require_gte!(total_quote_matched - taker_fee, quote_limit, ClobbyProgramError::MarketOrderSlippageExceeded);
+
+                let unsold_base_amount = remaining_order_amount + unfilled_base_amount;
+                user_balance_account.base_amount += unsold_base_amount;
+                msg!("refunded {} unsold base amount of the market order", unsold_base_amount);
+            }
+        }
+
+        remaining_order_amount = 0;
+    }
+
+    // a limit priced bid escrows its limit price for every matched base lot, the difference
+    // to what was actually paid to the makers is credited back to the user balance account
+    if args.side == Side::Bid && !matches!(args.order_type, OrderType::Market { .. }) {
+        let escrowed_quote_amount = args.quote_amount * (total_base_matched / market.base_lot_size);
+        let price_improvement = escrowed_quote_amount - total_quote_matched;
+
+        if price_improvement > 0 {
+            user_balance_account.quote_amount += price_improvement;
+            msg!("refunded {} quote amount as price improvement", price_improvement);
+        }
+    }
+
     // +1 for taker to sit on the orderbook
-    if !market_events.can_add_event(orders_matched + 1) {
+    if !market_events.can_add_event(orders_matched + orders_removed + 1) {
         return err!(ClobbyProgramError::EventsMaxLimit);
     }
 
@@ -138,46 +386,40 @@ pub fn place_order(ctx:Context<PlaceOrder>, args:PlaceOrderArgs) -> Result<()> {
 
     // apply the changes to the opposing_order acccounts
     // check if the remaining_order_amount > 0 then add it in orderbook
-    for (index, (order_id, total_quote_amount)) in orders_to_delete.iter().enumerate() {
-        let matched_order = &mut opposing_side.orders[index];
+    for order in orders_to_delete.iter() {
+        let matched_order = &mut opposing_side.orders[order.index];
+        require_eq!(matched_order.order_id, order.order_id, ClobbyProgramError::InvalidOrderId);
 
-        // to match the makers
+        // to match the makers, or pay back the expired ones
         market_events.add_event(EventParams{
             order_id: matched_order.order_id,
             maker: matched_order.order_authority,
+            maker_sub_account: matched_order.sub_account,
             base_amount: matched_order.base_amount,
-            quote_amount: *total_quote_amount,
-            event_type: EventType::Fill,
-            side: event_type_ops_side.clone() ,
+            quote_amount: order.total_quote_amount,
+            event_type: order.event_type.clone(),
+            side: event_type_ops_side,
         });
 
         // reset the order
-        if matched_order.order_id == *order_id {
-            matched_order.base_amount = 0;
-            matched_order.order_id = 0; // this is important
-            matched_order.quote_amount = 0;
-            matched_order.order_authority = market.key();
-        }
+        matched_order.remove(market.key());
     }
 
-    let full_orders_matched = orders_to_delete.len();
-
-    for (index, order) in orders_to_edit.iter().enumerate() {
-        let order_idx = full_orders_matched + index;
-        let partial_matched_order = &mut opposing_side.orders[order_idx];
+    for order in orders_to_edit.iter() {
+        let partial_matched_order = &mut opposing_side.orders[order.index];
+        require_eq!(partial_matched_order.order_id, order.order_id, ClobbyProgramError::InvalidOrderId);
 
-        if partial_matched_order.order_id == order.order_id {
-            partial_matched_order.base_amount = order.base_amount_to_set;
+        partial_matched_order.base_amount = order.base_amount_to_set;
 
-            market_events.add_event(EventParams{
-                base_amount: order.base_amount_to_set,
-                order_id: partial_matched_order.order_id,
-                maker: partial_matched_order.order_authority,
-                quote_amount: order.total_quote_amount,
-                event_type: EventType::Fill,
-                side: event_type_ops_side,
-            });
-        }
+        market_events.add_event(EventParams{
+            base_amount: order.base_amount_eaten,
+            order_id: partial_matched_order.order_id,
+            maker: partial_matched_order.order_authority,
+            maker_sub_account: partial_matched_order.sub_account,
+            quote_amount: order.total_quote_amount,
+            event_type: order.event_type.clone(),
+            side: event_type_ops_side,
+        });
     }
 
     /*
@@ -190,15 +432,31 @@ pub fn place_order(ctx:Context<PlaceOrder>, args:PlaceOrderArgs) -> Result<()> {
         Order{order_id: 0, base_amount:0, quote_amount: 0},
     */
 
-    // now reposition the array
+    // now reposition the array, a partially matched order still sits on the orderbook
+    opposing_side.compact_orders();
+    market.total_orders += 1;
 
-    for i in full_orders_matched as u64..opposing_side.order_count {
-        let j:u64 = i - full_orders_matched as u64;
-        opposing_side.orders.swap(i as usize, j as usize);
-    }
+    // the matching can stop before reaching every crossing order, once either of the limits is hit.
+    // the remaining should never sit on the orderbook crossing the best live opposing order
+    if remaining_order_amount > 0 {
+        let opposing_count = opposing_side.order_count as usize;
 
-    opposing_side.order_count -= orders_matched as u64;
-    market.total_orders += 1;
+        let best_opposing_order = opposing_side.orders[..opposing_count]
+        .iter()
+        .find(|order| !order.is_expired(&clock));
+
+        if let Some(best_opposing_order) = best_opposing_order {
+            if args.side.crosses(limit_price_in_ticks, best_opposing_order.price_in_ticks) {
+                if args.order_type == OrderType::PostOnly {
+                    return err!(ClobbyProgramError::PostOnlyWouldCross);
+                }
+
+                msg!("Cancelling the unfilled {} base amount of the order, as it would cross the orderbook", remaining_order_amount);
+                unfilled_base_amount += remaining_order_amount;
+                remaining_order_amount = 0;
+            }
+        }
+    }
 
 
     if remaining_order_amount == 0 {
@@ -206,30 +464,34 @@ pub fn place_order(ctx:Context<PlaceOrder>, args:PlaceOrderArgs) -> Result<()> {
     }
     else{
 
-        // if the book is full remove the last order
-        if orders_matched > 0 && taker_side.order_count == taker_side.orders.len() as u64{
+        // if the book is full remove the last order, whether the order matched or not.
+        // an order that does not improve on the last order can't take its place
+        if taker_side.order_count == taker_side.orders.len() as u64{
 
             let index = (taker_side.order_count - 1) as usize;
 
             let removed_order = taker_side.orders[index];
 
+            let improves_on_removed_order = match args.side {
+                Side::Bid => limit_price_in_ticks > removed_order.price_in_ticks,
+                Side::Ask => limit_price_in_ticks < removed_order.price_in_ticks,
+            };
+
+            require!(improves_on_removed_order, ClobbyProgramError::BookSideFull);
+
             // record the removed order in the market event, as we need to pay them back 
 
             market_events.add_event(EventParams { 
                 order_id: removed_order.order_id, 
                 maker: removed_order.order_authority, 
+                maker_sub_account: removed_order.sub_account, 
                 side: args.side, 
                 event_type: EventType::Out, 
                 base_amount: removed_order.base_amount, 
-                quote_amount: removed_order.quote_amount, 
+                quote_amount: removed_order.total_quote_amount(market), 
             });
 
-            taker_side.orders[index] = BookSideOrder{
-                base_amount:0,
-                order_authority:market.key(),
-                order_id: 0,
-                quote_amount: 0,
-            };
+            taker_side.orders[index].remove(market.key());
 
             taker_side.order_count-=1;
         }
@@ -240,9 +502,14 @@ pub fn place_order(ctx:Context<PlaceOrder>, args:PlaceOrderArgs) -> Result<()> {
 
         taker_side.orders[index] = BookSideOrder {
             base_amount: remaining_order_amount,
-            quote_amount: args.quote_amount,
+            price_in_ticks: limit_price_in_ticks,
             order_id,
-            order_authority: *accounts.user.key
+            order_authority: *accounts.user.key,
+            sub_account: args.sub_account,
+            padding: [0; 6],
+            client_order_id: args.client_order_id,
+            expires_at_timestamp,
+            expires_at_slot,
         };
 
         taker_side.order_count += 1;
@@ -250,11 +517,55 @@ pub fn place_order(ctx:Context<PlaceOrder>, args:PlaceOrderArgs) -> Result<()> {
         // sort it base on the side, once the order is added
         taker_side.sort_orders_till_idx(index)?;
 
+        match args.side {
+            Side::Bid => {
+                let resting_base_lots = remaining_order_amount / market.base_lot_size;
+                user_balance_account.quote_locked += market.bid_escrow(args.quote_amount, resting_base_lots);
+            },
+            Side::Ask => {
+                user_balance_account.base_locked += remaining_order_amount;
+            }
+        }
+
         msg!("Successfully executed the orders! and placed the remaining or orderbook");
 
     }
 
-    // finally transfer the token from the user token account to market vault account
+    // finally transfer the token from the user token account to market vault account,
+    // only the amount that got matched or placed on the orderbook is transferred
+    let escrowed_base_lots = (base_amount - unfilled_base_amount) / market.base_lot_size;
+    let matched_base_lots = total_base_matched / market.base_lot_size;
+    let resting_base_lots = remaining_order_amount / market.base_lot_size;
+
+    let mut transfer_token_amount = match (args.order_type, args.side) {
+        (OrderType::Market { quote_limit }, Side::Bid) => quote_limit + taker_fee,
+        (OrderType::Market { .. }, Side::Ask) => base_amount,
+        (_, Side::Bid) => {
+            args.quote_amount * matched_base_lots + taker_fee + market.bid_escrow(args.quote_amount, resting_base_lots)
+        },
+        (_, Side::Ask) => escrowed_base_lots * market.base_lot_size,
+    };
+
+    // the free balance is drawn first, only the shortfall is transferred from the user
+    if args.fund_from_balance {
+        let free_balance = match args.side {
+            Side::Bid => &mut user_balance_account.quote_amount,
+            Side::Ask => &mut user_balance_account.base_amount,
+        };
+
+        let amount_from_balance = transfer_token_amount.min(*free_balance);
+        *free_balance -= amount_from_balance;
+        transfer_token_amount -= amount_from_balance;
+
+        msg!("Funded {} of the escrow from the user balance account", amount_from_balance);
+    }
+
+    if transfer_token_amount == 0 {
+        msg!("Nothing to transfer to the market vault");
+        return Ok(());
+    }
+
+    require!(accounts.user_token_account.amount >= transfer_token_amount, ClobbyProgramError::InSufficientBalance);
 
     let decimals = accounts.token_to_trade.decimals;
 
@@ -287,11 +598,19 @@ pub struct PlaceOrder<'info>{
         mut,
         constraint = user.key() == user_balance_account.user.key(),
         constraint = user_balance_account.market.key() == market.key(),
-        seeds = [b"balance", user.key().as_ref()],
+        seeds = [b"balance", market.key().as_ref(), user.key().as_ref(), &args.sub_account.to_le_bytes()],
         bump,
     )]
     pub user_balance_account: Account<'info, UserBalance>,
 
+    /// Balance account of the front-end that routed the order, gets a share of the taker fee
+    #[account(
+        mut,
+        constraint = referrer_balance_account.market.key() == market.key(),
+        constraint = referrer_balance_account.user.key() != user.key(),
+    )]
+    pub referrer_balance_account: Option<Account<'info, UserBalance>>,
+
     #[account(
         mut,
         constraint = bids.key() == market.bids.key(),
@@ -338,10 +657,69 @@ pub struct PlaceOrder<'info>{
 }
 
 
+#[derive(AnchorDeserialize, AnchorSerialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum OrderType {
+    /// Match whatever crosses, and place the remaining on the orderbook
+    Limit,
+    /// Only ever sit on the orderbook, fails if the order would cross
+    PostOnly,
+    /// Match whatever crosses, and cancel the remaining instead of placing it
+    ImmediateOrCancel,
+    /// Fails unless the whole order gets matched
+    FillOrKill,
+    /// Match at any price, the quote_amount of the order is ignored.
+    /// For bids, quote_limit is the maximum quote amount to spend (excluding the taker fee),
+    /// for asks, it is the minimum quote amount to receive (after the taker fee)
+    Market { quote_limit: u64 },
+}
+
+#[derive(AnchorDeserialize, AnchorSerialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum OrderExpiry {
+    UnixTimestamp { timestamp: i64 },
+    Slot { slot: u64 },
+}
+
+impl OrderExpiry {
+    /// Returns the (expires_at_timestamp, expires_at_slot) to be stored on the order
+    pub fn resolve(expiry: Option<OrderExpiry>, clock: &Clock) -> Result<(i64, u64)> {
+        match expiry {
+            Some(OrderExpiry::UnixTimestamp { timestamp }) => {
+                require_gt!(timestamp, clock.unix_timestamp, ClobbyProgramError::OrderAlreadyExpired);
+                Ok((timestamp, 0))
+            },
+            Some(OrderExpiry::Slot { slot }) => {
+                require_gt!(slot, clock.slot, ClobbyProgramError::OrderAlreadyExpired);
+                Ok((0, slot))
+            },
+            None => Ok((0, 0)),
+        }
+    }
+}
+
+/// What to do when the order crosses a resting order of the same user
+#[derive(AnchorDeserialize, AnchorSerialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum SelfTradeBehavior {
+    /// Match against the own order, like any other order
+    Allow,
+    /// Fail the whole transaction
+    AbortTransaction,
+    /// Cancel the resting order, and continue matching
+    CancelMaker,
+    /// Stop matching, and cancel the remaining of the incoming order
+    CancelTaker,
+    /// Decrement both the orders by the overlapping amount without trading
+    DecrementAndCancel,
+}
+
 #[derive(AnchorDeserialize, AnchorSerialize)]
 pub struct PlaceOrderArgs {
     pub side: Side,
     pub base_lots: u16, // Number of base lots to buy or sell
-    pub quote_amount: u64,
-    pub ioc: bool, // ImmediateOrCancel 
+    pub quote_amount: u64, // Price of one base lot, should be in the multiples of the tick size
+    pub order_type: OrderType,
+    pub expiry: Option<OrderExpiry>, // None -> Good till cancelled
+    pub self_trade_behavior: SelfTradeBehavior,
+    pub client_order_id: u64,
+    pub sub_account: u16, // sub account of the user, the order is placed for
+    pub fund_from_balance: bool, // draw the escrow from the free balance of the user balance account first
 }
\ No newline at end of file
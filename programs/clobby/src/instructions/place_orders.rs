@@ -0,0 +1,234 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked};
+
+use crate::{errors::ClobbyProgramError, state::{BookSide, BookSideOrder, Market, Side, UserBalance}};
+
+use super::OrderExpiry;
+
+/// Places a batch of orders on both the sides, all the orders only ever sit on the orderbook
+/// (same as post only), so the whole batch fails if any of the orders would cross
+pub fn place_orders(ctx:Context<PlaceOrders>, args: PlaceOrdersArgs) -> Result<()> {
+
+    let accounts = ctx.accounts;
+
+    let (total_quote_to_escrow, total_base_to_escrow) = {
+        let mut bids = accounts.bids.load_mut()?;
+        let mut asks = accounts.asks.load_mut()?;
+
+        insert_orders(&mut accounts.market, &mut bids, &mut asks, accounts.user.key(), args.sub_account, &args.orders)?
+    };
+
+    let user_balance_account = &mut accounts.user_balance_account;
+    user_balance_account.quote_locked += total_quote_to_escrow;
+    user_balance_account.base_locked += total_base_to_escrow;
+
+    require!(accounts.user_quote_token_account.amount >= total_quote_to_escrow, ClobbyProgramError::InSufficientBalance);
+    require!(accounts.user_base_token_account.amount >= total_base_to_escrow, ClobbyProgramError::InSufficientBalance);
+
+    // finally transfer the escrow of each side, with a single transfer per mint
+    let cpi_program = accounts.token_program.to_account_info();
+
+    if total_quote_to_escrow > 0 {
+        let cpi_accounts = TransferChecked {
+            mint: accounts.quote_token.to_account_info(),
+            from: accounts.user_quote_token_account.to_account_info(),
+            to: accounts.quote_token_vault.to_account_info(),
+            authority: accounts.user.to_account_info(),
+        };
+
+        let cpi_context = CpiContext::new(cpi_program.clone(), cpi_accounts);
+        transfer_checked(cpi_context, total_quote_to_escrow, accounts.quote_token.decimals)?;
+    }
+
+    if total_base_to_escrow > 0 {
+        let cpi_accounts = TransferChecked {
+            mint: accounts.base_token.to_account_info(),
+            from: accounts.user_base_token_account.to_account_info(),
+            to: accounts.base_token_vault.to_account_info(),
+            authority: accounts.user.to_account_info(),
+        };
+
+        let cpi_context = CpiContext::new(cpi_program, cpi_accounts);
+        transfer_checked(cpi_context, total_base_to_escrow, accounts.base_token.decimals)?;
+    }
+
+    Ok(())
+}
+
+/// Inserts the orders at the end of each side and sorts each side only once.
+/// Fails if any of the orders would cross, returns the (quote, base) amount to be escrowed
+pub(crate) fn insert_orders(
+    market: &mut Market, 
+    bids: &mut BookSide, 
+    asks: &mut BookSide, 
+    user: Pubkey, 
+    sub_account: u16,
+    orders: &[BatchOrderArgs],
+) -> Result<(u64, u64)> {
+
+    let clock = Clock::get()?;
+
+    let mut total_quote_to_escrow = 0_u64;
+    let mut total_base_to_escrow = 0_u64;
+    let mut new_bids_count = 0_usize;
+    let mut new_asks_count = 0_usize;
+
+    // the best prices once the batch is placed, none of the bids should cross any of the asks.
+    // expired orders are skipped, as they would be removed instead of matched
+    let best_live_price = |bookside: &BookSide| {
+        bookside.orders[..bookside.order_count as usize]
+        .iter()
+        .find(|order| !order.is_expired(&clock))
+        .map(|order| order.price_in_ticks)
+    };
+
+    let mut best_bid_price = best_live_price(bids);
+    let mut best_ask_price = best_live_price(asks);
+
+    for order in orders.iter() {
+
+        market.validate_order_size(u64::from(order.base_lots), Some(order.quote_amount * u64::from(order.base_lots)))?;
+
+        let price_in_ticks = market.quote_amount_to_ticks(order.quote_amount)?;
+
+        match order.side {
+            Side::Bid => {
+                total_quote_to_escrow += market.bid_escrow(order.quote_amount, u64::from(order.base_lots));
+                best_bid_price = Some(best_bid_price.map_or(price_in_ticks, |price| price.max(price_in_ticks)));
+                new_bids_count += 1;
+            },
+            Side::Ask => {
+                total_base_to_escrow += u64::from(order.base_lots) * market.base_lot_size;
+                best_ask_price = Some(best_ask_price.map_or(price_in_ticks, |price| price.min(price_in_ticks)));
+                new_asks_count += 1;
+            }
+        }
+    }
+
+    if let (Some(bid_price), Some(ask_price)) = (best_bid_price, best_ask_price) {
+        if Side::Bid.crosses(bid_price, ask_price) {
+            return err!(ClobbyProgramError::PostOnlyWouldCross);
+        }
+    }
+
+    require!(bids.order_count as usize + new_bids_count <= bids.orders.len(), ClobbyProgramError::BookSideFull);
+    require!(asks.order_count as usize + new_asks_count <= asks.orders.len(), ClobbyProgramError::BookSideFull);
+
+    for order in orders.iter() {
+
+        let (expires_at_timestamp, expires_at_slot) = OrderExpiry::resolve(order.expiry, &clock)?;
+
+        market.total_orders += 1;
+
+        let bookside: &mut BookSide = match order.side {
+            Side::Bid => bids,
+            Side::Ask => asks,
+        };
+
+        let index = bookside.order_count as usize;
+
+        bookside.orders[index] = BookSideOrder {
+            base_amount: u64::from(order.base_lots) * market.base_lot_size,
+            price_in_ticks: market.quote_amount_to_ticks(order.quote_amount)?,
+            order_id: market.total_orders,
+            order_authority: user,
+            sub_account,
+            padding: [0; 6],
+            client_order_id: order.client_order_id,
+            expires_at_timestamp,
+            expires_at_slot,
+        };
+
+        bookside.order_count += 1;
+    }
+
+    if new_bids_count > 0 {
+        let till_order_idx = (bids.order_count - 1) as usize;
+        bids.sort_orders_till_idx(till_order_idx)?;
+    }
+
+    if new_asks_count > 0 {
+        let till_order_idx = (asks.order_count - 1) as usize;
+        asks.sort_orders_till_idx(till_order_idx)?;
+    }
+
+    msg!("Successfully placed {} bids and {} asks", new_bids_count, new_asks_count);
+
+    Ok((total_quote_to_escrow, total_base_to_escrow))
+}
+
+#[derive(Accounts)]
+#[instruction(args: PlaceOrdersArgs)]
+pub struct PlaceOrders<'info>{
+
+    #[account(
+        mut,
+        signer,
+    )]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = user.key() == user_balance_account.user.key(),
+        constraint = user_balance_account.market.key() == market.key(),
+        seeds = [b"balance", market.key().as_ref(), user.key().as_ref(), &args.sub_account.to_le_bytes()],
+        bump,
+    )]
+    pub user_balance_account: Account<'info, UserBalance>,
+
+    #[account(
+        mut,
+        has_one = bids,
+        has_one = asks,
+        has_one = base_token,
+        has_one = quote_token,
+        has_one = base_token_vault,
+        has_one = quote_token_vault,
+    )]
+    pub market: Box<Account<'info, Market>>,
+
+    #[account(mut)]
+    pub bids: AccountLoader<'info, BookSide>,
+
+    #[account(mut)]
+    pub asks: AccountLoader<'info, BookSide>,
+
+    #[account(
+        mut,
+        token::authority = user,
+        token::mint = base_token,
+    )]
+    pub user_base_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        token::authority = user,
+        token::mint = quote_token,
+    )]
+    pub user_quote_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub base_token_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub quote_token_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub base_token: Box<InterfaceAccount<'info, Mint>>,
+    pub quote_token: Box<InterfaceAccount<'info, Mint>>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(AnchorDeserialize, AnchorSerialize, Clone)]
+pub struct BatchOrderArgs {
+    pub side: Side,
+    pub base_lots: u16, // Number of base lots to buy or sell
+    pub quote_amount: u64, // Price of one base lot, should be in the multiples of the tick size
+    pub expiry: Option<OrderExpiry>, // None -> Good till cancelled
+    pub client_order_id: u64,
+}
+
+#[derive(AnchorDeserialize, AnchorSerialize)]
+pub struct PlaceOrdersArgs {
+    pub orders: Vec<BatchOrderArgs>,
+    pub sub_account: u16, // sub account of the user, the orders are placed for
+}
@@ -0,0 +1,97 @@
+use anchor_lang::prelude::*;
+
+use crate::{errors::ClobbyProgramError, state::{BookSide, EventParams, EventType, Market, MarketEvents, Side}};
+
+pub fn prune_expired_orders(ctx:Context<PruneExpiredOrders>, args: PruneExpiredOrdersArgs) -> Result<()> {
+
+    let accounts = ctx.accounts;
+
+    let bookside_account = &accounts.bookside_account;
+    let mut bookside = bookside_account.load_mut()?;
+    let mut market_events = accounts.market_events.load_mut()?;
+
+    let expected_bookside = match args.side {
+        Side::Bid => {
+            accounts.market.bids.key()
+        },
+        Side::Ask => {
+            accounts.market.asks.key()
+        }
+    };
+
+    require_keys_eq!(expected_bookside, bookside_account.key());
+
+    let clock = Clock::get()?;
+    let order_count = bookside.order_count as usize;
+
+    let orders_to_prune = bookside.orders[..order_count]
+    .iter()
+    .filter(|order| order.is_expired(&clock))
+    .count()
+    .min(args.limit as usize);
+
+    if orders_to_prune == 0 {
+        msg!("No expired orders to prune");
+        return Ok(());
+    }
+
+    if !market_events.can_add_event(orders_to_prune) {
+        return err!(ClobbyProgramError::EventsMaxLimit);
+    }
+
+    let mut pruned_count = 0;
+
+    for order in bookside.orders[..order_count].iter_mut() {
+
+        if pruned_count == orders_to_prune {
+            break;
+        }
+
+        if !order.is_expired(&clock) {
+            continue;
+        }
+
+        // the maker gets back the escrow, once the out event is consumed
+        market_events.add_event(EventParams {
+            order_id: order.order_id,
+            maker: order.order_authority,
+            maker_sub_account: order.sub_account,
+            side: args.side,
+            event_type: EventType::Out,
+            base_amount: order.base_amount,
+            quote_amount: order.total_quote_amount(&accounts.market),
+        });
+
+        order.remove(accounts.market.key());
+        pruned_count += 1;
+    }
+
+    bookside.compact_orders();
+
+    msg!("successfully pruned {} expired orders", pruned_count);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct PruneExpiredOrders<'info>{
+
+    #[account(
+        has_one = market_events,
+    )]
+    pub market: Box<Account<'info, Market>>,
+
+    #[account(
+        mut
+    )]
+    pub market_events: AccountLoader<'info, MarketEvents>,
+
+    #[account(mut)]
+    pub bookside_account: AccountLoader<'info, BookSide>,
+}
+
+#[derive(AnchorDeserialize, AnchorSerialize)]
+pub struct PruneExpiredOrdersArgs{
+    pub side: Side,
+    pub limit: u16, // Maximum number of expired orders to prune
+}
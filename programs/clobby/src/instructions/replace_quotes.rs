@@ -0,0 +1,162 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked};
+
+use crate::{errors::ClobbyProgramError, state::{BookSide, BookSideOrder, Market, UserBalance}};
+
+use super::{insert_orders, BatchOrderArgs};
+
+/// Cancels all the resting orders of the user on both the sides, and places the new orders.
+/// The refunded escrow is netted against the new escrow inside the user balance account, 
+/// so no out events are recorded and only the shortfall is transferred from the user
+pub fn replace_quotes(ctx:Context<ReplaceQuotes>, args: ReplaceQuotesArgs) -> Result<()> {
+
+    let accounts = ctx.accounts;
+
+    let user = accounts.user.key();
+    let market_key = accounts.market.key();
+    let user_balance_account = &mut accounts.user_balance_account;
+    let sub_account = args.sub_account;
+    let is_user_order = |order: &BookSideOrder| order.order_authority == user && order.sub_account == sub_account;
+
+    let (total_quote_to_escrow, total_base_to_escrow) = {
+        let mut bids = accounts.bids.load_mut()?;
+        let mut asks = accounts.asks.load_mut()?;
+
+        let mut cancelled_count = 0;
+
+        let bids_count = bids.order_count as usize;
+        let asks_count = asks.order_count as usize;
+
+        for order in bids.orders[..bids_count].iter_mut().filter(|order| is_user_order(order)) {
+            user_balance_account.quote_amount += order.total_quote_amount(&accounts.market);
+            user_balance_account.quote_locked -= order.total_quote_amount(&accounts.market);
+            order.remove(market_key);
+            cancelled_count += 1;
+        }
+
+        for order in asks.orders[..asks_count].iter_mut().filter(|order| is_user_order(order)) {
+            user_balance_account.base_amount += order.base_amount;
+            user_balance_account.base_locked -= order.base_amount;
+            order.remove(market_key);
+            cancelled_count += 1;
+        }
+
+        bids.compact_orders();
+        asks.compact_orders();
+
+        msg!("Cancelled {} orders", cancelled_count);
+
+        insert_orders(&mut accounts.market, &mut bids, &mut asks, user, sub_account, &args.orders)?
+    };
+
+    user_balance_account.quote_locked += total_quote_to_escrow;
+    user_balance_account.base_locked += total_base_to_escrow;
+
+    // draw the new escrow from the user balance account first
+    let quote_from_balance = total_quote_to_escrow.min(user_balance_account.quote_amount);
+    let base_from_balance = total_base_to_escrow.min(user_balance_account.base_amount);
+
+    user_balance_account.quote_amount -= quote_from_balance;
+    user_balance_account.base_amount -= base_from_balance;
+
+    let quote_to_transfer = total_quote_to_escrow - quote_from_balance;
+    let base_to_transfer = total_base_to_escrow - base_from_balance;
+
+    require!(accounts.user_quote_token_account.amount >= quote_to_transfer, ClobbyProgramError::InSufficientBalance);
+    require!(accounts.user_base_token_account.amount >= base_to_transfer, ClobbyProgramError::InSufficientBalance);
+
+    let cpi_program = accounts.token_program.to_account_info();
+
+    if quote_to_transfer > 0 {
+        let cpi_accounts = TransferChecked {
+            mint: accounts.quote_token.to_account_info(),
+            from: accounts.user_quote_token_account.to_account_info(),
+            to: accounts.quote_token_vault.to_account_info(),
+            authority: accounts.user.to_account_info(),
+        };
+
+        let cpi_context = CpiContext::new(cpi_program.clone(), cpi_accounts);
+        transfer_checked(cpi_context, quote_to_transfer, accounts.quote_token.decimals)?;
+    }
+
+    if base_to_transfer > 0 {
+        let cpi_accounts = TransferChecked {
+            mint: accounts.base_token.to_account_info(),
+            from: accounts.user_base_token_account.to_account_info(),
+            to: accounts.base_token_vault.to_account_info(),
+            authority: accounts.user.to_account_info(),
+        };
+
+        let cpi_context = CpiContext::new(cpi_program, cpi_accounts);
+        transfer_checked(cpi_context, base_to_transfer, accounts.base_token.decimals)?;
+    }
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(args: ReplaceQuotesArgs)]
+pub struct ReplaceQuotes<'info>{
+
+    #[account(
+        mut,
+        signer,
+    )]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = user.key() == user_balance_account.user.key(),
+        constraint = user_balance_account.market.key() == market.key(),
+        seeds = [b"balance", market.key().as_ref(), user.key().as_ref(), &args.sub_account.to_le_bytes()],
+        bump,
+    )]
+    pub user_balance_account: Account<'info, UserBalance>,
+
+    #[account(
+        mut,
+        has_one = bids,
+        has_one = asks,
+        has_one = base_token,
+        has_one = quote_token,
+        has_one = base_token_vault,
+        has_one = quote_token_vault,
+    )]
+    pub market: Box<Account<'info, Market>>,
+
+    #[account(mut)]
+    pub bids: AccountLoader<'info, BookSide>,
+
+    #[account(mut)]
+    pub asks: AccountLoader<'info, BookSide>,
+
+    #[account(
+        mut,
+        token::authority = user,
+        token::mint = base_token,
+    )]
+    pub user_base_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        token::authority = user,
+        token::mint = quote_token,
+    )]
+    pub user_quote_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub base_token_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub quote_token_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub base_token: Box<InterfaceAccount<'info, Mint>>,
+    pub quote_token: Box<InterfaceAccount<'info, Mint>>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(AnchorDeserialize, AnchorSerialize)]
+pub struct ReplaceQuotesArgs {
+    pub orders: Vec<BatchOrderArgs>,
+    pub sub_account: u16, // only the orders of this sub account are replaced
+}
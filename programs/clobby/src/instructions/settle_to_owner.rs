@@ -0,0 +1,108 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+
+use crate::{errors::ClobbyProgramError, state::{Market, UserBalance}};
+
+use super::{transfer_from_balance, BalanceTransfer};
+
+/// Same as settle_user_balance, but cranked by the market operators without the owner's signature.
+/// The whole balance can only ever go to the owner's associated token accounts, which are
+/// only required for the sides that hold a balance
+pub fn settle_to_owner(ctx:Context<SettleToOwner>) -> Result<()>{
+
+    let accounts = ctx.accounts;
+
+    let base_to_settle = accounts.user_balance_account.base_amount;
+    let quote_to_settle = accounts.user_balance_account.quote_amount;
+
+    transfer_from_balance(
+        &accounts.market,
+        accounts.market_authority.to_account_info(),
+        accounts.token_program.to_account_info(),
+        &mut accounts.user_balance_account,
+        BalanceTransfer {
+            amount: base_to_settle,
+            mint: &accounts.base_token,
+            vault: accounts.base_vault_account.to_account_info(),
+            destination: accounts.owner_base_token_account.as_ref().map(|account| account.to_account_info()),
+        },
+        BalanceTransfer {
+            amount: quote_to_settle,
+            mint: &accounts.quote_token,
+            vault: accounts.quote_vault_account.to_account_info(),
+            destination: accounts.owner_quote_token_account.as_ref().map(|account| account.to_account_info()),
+        },
+    )?;
+
+    msg!("Settled the balance of {} to its associated token accounts", accounts.user_balance_account.user);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct SettleToOwner<'info>{
+
+    /// Either the admin or the consume events authority of the market
+    #[account(
+        constraint = cranker.key() == market.admin || cranker.key() == market.consume_events_authority @ ClobbyProgramError::UnauthorizedCranker,
+    )]
+    pub cranker: Signer<'info>,
+
+    #[account(
+        has_one = base_token,
+        has_one = quote_token,
+        has_one = market_authority,
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: PDA of the market account, that can
+    /// transfer tokens,
+    #[account(
+        seeds=[b"market", market.key().as_ref()],
+        bump=market.market_authority_bump,
+    )]
+    pub market_authority: UncheckedAccount<'info>,
+
+    pub base_token: InterfaceAccount<'info, Mint>,
+    pub quote_token: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = user_balance_account.market.key() == market.key(),
+    )]
+    pub user_balance_account: Account<'info, UserBalance>,
+
+    #[account(
+        mut,
+        associated_token::mint = base_token,
+        associated_token::authority = user_balance_account.user,
+        associated_token::token_program = token_program,
+    )]
+    pub owner_base_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        associated_token::mint = quote_token,
+        associated_token::authority = user_balance_account.user,
+        associated_token::token_program = token_program,
+    )]
+    pub owner_quote_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        associated_token::mint = base_token,
+        associated_token::authority = market.market_authority,
+        associated_token::token_program = token_program,
+    )]
+    pub base_vault_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = quote_token,
+        associated_token::authority = market.market_authority,
+        associated_token::token_program = token_program,
+    )]
+    pub quote_vault_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
@@ -1,65 +1,46 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
 
-use crate::state::{Market, ResetSide, UserBalance};
+use crate::state::{Market, UserBalance};
 
+use super::{transfer_from_balance, BalanceTransfer};
 
-pub fn settle_user_balance(ctx:Context<SettleUserBalance>) -> Result<()>{
 
-    let accounts = ctx.accounts;
-
-    let balance_account = &mut accounts.user_balance_account;
-
-    let settle_base_token = if balance_account.base_amount > 0 {true} else {false};
-    let settle_quote_token = if balance_account.quote_amount > 0 {true} else {false};
-
-    let market_key = accounts.market.key();
-
-    let signer_seeds:&[&[&[u8]]] = &[&[b"market", market_key.as_ref(), &[accounts.market.market_authority_bump]]];
-        
-    let cpi_program = accounts.token_progam.to_account_info();
-    
-    if settle_base_token {
-
-        let base_cpi_accounts = TransferChecked {
-            mint: accounts.base_token.to_account_info(),
-            from: accounts.base_vault_account.to_account_info(),
-            to: accounts.user_base_token_account.to_account_info(),
-            authority: accounts.market_authority.to_account_info(),
-        };
-
-        let cpi_context = CpiContext::new(cpi_program.clone(), base_cpi_accounts).with_signer(signer_seeds);
+/// Same as withdraw, but settles the whole balance by default, and
+/// the destination token accounts can be owned by anyone
+pub fn settle_user_balance(ctx:Context<SettleUserBalance>, args: SettleUserBalanceArgs) -> Result<()>{
 
-        token_interface::transfer_checked(cpi_context, balance_account.base_amount, accounts.base_token.decimals)?;
-
-        balance_account.reset_balance(ResetSide::Base);
-    }
-    else{
-        msg!("No base token to settle");
-    }
-
-    if settle_quote_token {
-        let quote_cpi_accounts = TransferChecked {
-            mint: accounts.quote_token.to_account_info(),
-            from: accounts.quote_vault_account.to_account_info(),
-            to: accounts.user_quote_token_account.to_account_info(),
-            authority: accounts.market_authority.to_account_info(),
-        };
-
-        let cpi_context = CpiContext::new(cpi_program, quote_cpi_accounts).with_signer(signer_seeds);
+    let accounts = ctx.accounts;
 
-        token_interface::transfer_checked(cpi_context, balance_account.quote_amount, accounts.quote_token.decimals)?;
+    let base_to_settle = args.base_amount.unwrap_or(accounts.user_balance_account.base_amount);
+    let quote_to_settle = args.quote_amount.unwrap_or(accounts.user_balance_account.quote_amount);
+
+    transfer_from_balance(
+        &accounts.market,
+        accounts.market_authority.to_account_info(),
+        accounts.token_progam.to_account_info(),
+        &mut accounts.user_balance_account,
+        BalanceTransfer {
+            amount: base_to_settle,
+            mint: &accounts.base_token,
+            vault: accounts.base_vault_account.to_account_info(),
+            destination: Some(accounts.base_destination_account.to_account_info()),
+        },
+        BalanceTransfer {
+            amount: quote_to_settle,
+            mint: &accounts.quote_token,
+            vault: accounts.quote_vault_account.to_account_info(),
+            destination: Some(accounts.quote_destination_account.to_account_info()),
+        },
+    )?;
+
+    msg!("Settled {} base amount and {} quote amount", base_to_settle, quote_to_settle);
 
-        balance_account.reset_balance(ResetSide::Quote);
-    }
-    else{
-        msg!("No quote token to settle");
-    }
-    
     Ok(())
 }
 
 #[derive(Accounts)]
+#[instruction(args: SettleUserBalanceArgs)]
 pub struct SettleUserBalance<'info>{
 
     #[account(
@@ -90,23 +71,25 @@ pub struct SettleUserBalance<'info>{
     #[account(
         mut,
         has_one = user,
-        constraint = user_balance_account.market.key() == market.key()
+        constraint = user_balance_account.market.key() == market.key(),
+        seeds = [b"balance", market.key().as_ref(), user.key().as_ref(), &args.sub_account.to_le_bytes()],
+        bump,
     )]
     pub user_balance_account: Account<'info, UserBalance>,
 
+    /// can be owned by anyone, i.e an exchange deposit address
     #[account(
         mut,
         token::mint = base_token.key(),
-        token::authority = user.key(),
     )]
-    pub user_base_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub base_destination_account: InterfaceAccount<'info, TokenAccount>,
 
+    /// can be owned by anyone, i.e an exchange deposit address
     #[account(
         mut,
         token::mint = quote_token.key(),
-        token::authority = user.key(),
     )]
-    pub user_quote_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub quote_destination_account: InterfaceAccount<'info, TokenAccount>,
 
     #[account(
         mut,
@@ -126,4 +109,11 @@ pub struct SettleUserBalance<'info>{
     pub quote_vault_account: InterfaceAccount<'info, TokenAccount>,
     pub token_progam: Interface<'info, TokenInterface>,
 
-}
\ No newline at end of file
+}
+
+#[derive(AnchorDeserialize, AnchorSerialize)]
+pub struct SettleUserBalanceArgs{
+    pub sub_account: u16, // sub account of the user, to settle
+    pub base_amount: Option<u64>, // None -> settle the whole base balance
+    pub quote_amount: Option<u64>, // None -> settle the whole quote balance
+}
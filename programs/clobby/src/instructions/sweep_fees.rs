@@ -0,0 +1,77 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+
+use crate::state::Market;
+
+pub fn sweep_fees(ctx:Context<SweepFees>) -> Result<()> {
+
+    let accounts = ctx.accounts;
+
+    let fees_to_sweep = accounts.market.fees_accrued;
+
+    if fees_to_sweep == 0 {
+        msg!("No fees to sweep");
+        return Ok(());
+    }
+
+    let market_key = accounts.market.key();
+
+    let signer_seeds:&[&[&[u8]]] = &[&[b"market", market_key.as_ref(), &[accounts.market.market_authority_bump]]];
+
+    let cpi_accounts = TransferChecked {
+        mint: accounts.quote_token.to_account_info(),
+        from: accounts.quote_token_vault.to_account_info(),
+        to: accounts.fee_destination.to_account_info(),
+        authority: accounts.market_authority.to_account_info(),
+    };
+
+    let cpi_context = CpiContext::new(accounts.token_program.to_account_info(), cpi_accounts).with_signer(signer_seeds);
+
+    token_interface::transfer_checked(cpi_context, fees_to_sweep, accounts.quote_token.decimals)?;
+
+    accounts.market.fees_accrued = 0;
+
+    msg!("swept {} quote amount of fees", fees_to_sweep);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct SweepFees<'info>{
+
+    #[account(
+        mut,
+        signer,
+    )]
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        has_one = admin,
+        has_one = quote_token,
+        has_one = quote_token_vault,
+        has_one = market_authority,
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: PDA of the market account, that can
+    /// transfer tokens,
+    #[account(
+        seeds=[b"market", market.key().as_ref()],
+        bump=market.market_authority_bump,
+    )]
+    pub market_authority: UncheckedAccount<'info>,
+
+    pub quote_token: InterfaceAccount<'info, Mint>,
+
+    #[account(mut)]
+    pub quote_token_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        token::mint = quote_token,
+    )]
+    pub fee_destination: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
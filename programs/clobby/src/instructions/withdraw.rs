@@ -0,0 +1,152 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked};
+
+use crate::{errors::ClobbyProgramError, state::{Market, UserBalance}};
+
+/// Transfers the given amounts out of the free balance of the user balance account,
+/// the amounts locked in the resting orders can not be withdrawn
+pub fn withdraw(ctx:Context<Withdraw>, args: WithdrawArgs) -> Result<()> {
+
+    let accounts = ctx.accounts;
+
+    transfer_from_balance(
+        &accounts.market,
+        accounts.market_authority.to_account_info(),
+        accounts.token_program.to_account_info(),
+        &mut accounts.user_balance_account,
+        BalanceTransfer {
+            amount: args.base_amount,
+            mint: &accounts.base_token,
+            vault: accounts.base_token_vault.to_account_info(),
+            destination: Some(accounts.user_base_token_account.to_account_info()),
+        },
+        BalanceTransfer {
+            amount: args.quote_amount,
+            mint: &accounts.quote_token,
+            vault: accounts.quote_token_vault.to_account_info(),
+            destination: Some(accounts.user_quote_token_account.to_account_info()),
+        },
+    )?;
+
+    msg!("Withdrew {} base amount and {} quote amount", args.base_amount, args.quote_amount);
+
+    Ok(())
+}
+
+/// Amount of one token to transfer out of the user balance account, from the market vault to the destination
+pub(crate) struct BalanceTransfer<'a, 'info> {
+    pub amount: u64,
+    pub mint: &'a InterfaceAccount<'info, Mint>,
+    pub vault: AccountInfo<'info>,
+    pub destination: Option<AccountInfo<'info>>, // Only required when the amount is non-zero
+}
+
+/// Debits the amounts from the free balance of the user balance account, and transfers them
+/// out of the market vaults, signed by the market authority
+pub(crate) fn transfer_from_balance<'info>(
+    market: &Account<'info, Market>,
+    market_authority: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+    user_balance_account: &mut UserBalance,
+    base: BalanceTransfer<'_, 'info>,
+    quote: BalanceTransfer<'_, 'info>,
+) -> Result<()> {
+
+    require_gte!(user_balance_account.base_amount, base.amount, ClobbyProgramError::InSufficientBalance);
+    require_gte!(user_balance_account.quote_amount, quote.amount, ClobbyProgramError::InSufficientBalance);
+
+    user_balance_account.base_amount -= base.amount;
+    user_balance_account.quote_amount -= quote.amount;
+
+    let market_key = market.key();
+
+    let signer_seeds:&[&[&[u8]]] = &[&[b"market", market_key.as_ref(), &[market.market_authority_bump]]];
+
+    for transfer in [base, quote] {
+        if transfer.amount == 0 {
+            continue;
+        }
+
+        let destination = transfer.destination.ok_or(ClobbyProgramError::MissingTokenAccount)?;
+
+        let cpi_accounts = TransferChecked {
+            mint: transfer.mint.to_account_info(),
+            from: transfer.vault,
+            to: destination,
+            authority: market_authority.clone(),
+        };
+
+        let cpi_context = CpiContext::new(token_program.clone(), cpi_accounts).with_signer(signer_seeds);
+        transfer_checked(cpi_context, transfer.amount, transfer.mint.decimals)?;
+    }
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(args: WithdrawArgs)]
+pub struct Withdraw<'info>{
+
+    #[account(
+        mut,
+        signer,
+    )]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = user.key() == user_balance_account.user.key(),
+        constraint = user_balance_account.market.key() == market.key(),
+        seeds = [b"balance", market.key().as_ref(), user.key().as_ref(), &args.sub_account.to_le_bytes()],
+        bump,
+    )]
+    pub user_balance_account: Account<'info, UserBalance>,
+
+    #[account(
+        has_one = base_token,
+        has_one = quote_token,
+        has_one = base_token_vault,
+        has_one = quote_token_vault,
+        has_one = market_authority,
+    )]
+    pub market: Box<Account<'info, Market>>,
+
+    /// CHECK: PDA of the market account, that can
+    /// transfer tokens,
+    #[account(
+        seeds=[b"market", market.key().as_ref()],
+        bump=market.market_authority_bump,
+    )]
+    pub market_authority: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        token::authority = user,
+        token::mint = base_token,
+    )]
+    pub user_base_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        token::authority = user,
+        token::mint = quote_token,
+    )]
+    pub user_quote_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub base_token_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub quote_token_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub base_token: Box<InterfaceAccount<'info, Mint>>,
+    pub quote_token: Box<InterfaceAccount<'info, Mint>>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(AnchorDeserialize, AnchorSerialize)]
+pub struct WithdrawArgs {
+    pub base_amount: u64, // native base tokens to withdraw
+    pub quote_amount: u64, // native quote tokens to withdraw
+    pub sub_account: u16,
+}
@@ -34,11 +34,50 @@ pub mod clobby {
         Ok(())
     }
 
+    /// Lets the market makers quote many levels on both the sides at once, 
+    /// with a single transfer per mint and a single sort per side
+    pub fn place_orders(ctx:Context<PlaceOrders>, args: PlaceOrdersArgs) -> Result<()> {
+        instructions::place_orders(ctx, args)?;
+        Ok(())
+    }
+
+    /// Atomically swaps the whole quote set of the user, every resting order of the user
+    /// is cancelled and the new orders are placed (same as post only). 
+    /// 
+    /// NOTE: The refunded escrow is netted inside the user balance account, without any out events
+    pub fn replace_quotes(ctx:Context<ReplaceQuotes>, args: ReplaceQuotesArgs) -> Result<()> {
+        instructions::replace_quotes(ctx, args)?;
+        Ok(())
+    }
+
     pub fn cancel_order(ctx:Context<CancelOrder>, args:CancelOrderArgs) -> Result<()>{
         instructions::cancel_order(ctx, args)?;
         Ok(())
     }
 
+    /// Lets the user cancel an order with its own client_order_id, without
+    /// waiting to learn the order_id assigned by the program
+    pub fn cancel_order_by_client_id(ctx:Context<CancelOrder>, args:CancelOrderByClientIdArgs) -> Result<()>{
+        instructions::cancel_order_by_client_id(ctx, args)?;
+        Ok(())
+    }
+
+    /// Cancels every order of the user on one or both the sides in a single transaction,
+    /// the escrow is paid back through out events like cancel_order
+    pub fn cancel_all_orders(ctx:Context<CancelAllOrders>, args:CancelAllOrdersArgs) -> Result<()>{
+        instructions::cancel_all_orders(ctx, args)?;
+        Ok(())
+    }
+
+    /// Changes the price and/or size of a resting order atomically. The order keeps its
+    /// time priority, only when the size is reduced at the same price.
+    /// 
+    /// NOTE: The escrow difference is netted against the user balance account
+    pub fn amend_order(ctx:Context<AmendOrder>, args:AmendOrderArgs) -> Result<()>{
+        instructions::amend_order(ctx, args)?;
+        Ok(())
+    }
+
     /// Whenever the taker is matched against the maker, the taker does not know the
     /// maker's balance account during matching. So whenever a match happens we record 
     /// it in market_events. Later when consumed_events ins is called, the makers balance
@@ -46,20 +85,64 @@ pub mod clobby {
     /// 
     /// NOTE: We only increase the balance, and not transfer the tokens, transferring occurs in
     /// settle_user_balance ins
-    pub fn consume_events<'a, 'b, 'c, 'info>(ctx:Context<'a, 'b, 'c, 'info, ConsumeEvents<'info>>) -> Result<()> where 'c : 'info {
-        instructions::consume_events(ctx)?;
+    /// 
+    /// The events are consumed in order, the crank stops at the first event whose maker balance
+    /// account is not passed in the remaining accounts. Returns the missing balance accounts of
+    /// the next events, so the cranker can pass them in the next call
+    pub fn consume_events<'a, 'b, 'c, 'info>(ctx:Context<'a, 'b, 'c, 'info, ConsumeEvents<'info>>) -> Result<Vec<Pubkey>> where 'c : 'info {
+        instructions::consume_events(ctx)
+    }
+
+    /// Expired orders are only removed when a taker walks over them, so anyone can 
+    /// crank this to free up the bookside. The makers get back their escrow through out events
+    pub fn prune_expired_orders(ctx:Context<PruneExpiredOrders>, args:PruneExpiredOrdersArgs) -> Result<()> {
+        instructions::prune_expired_orders(ctx, args)?;
+        Ok(())
+    }
+
+    /// Transfers the fees accrued by the market out of the quote vault, only the admin 
+    /// of the market can sweep them
+    pub fn sweep_fees(ctx:Context<SweepFees>) -> Result<()> {
+        instructions::sweep_fees(ctx)?;
         Ok(())
     }
 
     /// This is specially useful when matching the orders, we can directly increase or decrease the tokens
     /// we can settle the final amount, when the user requests for it.
-    pub fn create_user_balance_account(ctx:Context<CreateUserBalanceAccount>) -> Result<()> {
-        instructions::create_user_balance_account(ctx)?;
+    pub fn create_user_balance_account(ctx:Context<CreateUserBalanceAccount>, args: CreateUserBalanceAccountArgs) -> Result<()> {
+        instructions::create_user_balance_account(ctx, args)?;
+        Ok(())
+    }
+
+    /// Closes the user balance account and returns the rent to the user, only once the balance 
+    /// is settled and the user does not have any resting orders on the market
+    pub fn close_user_balance_account(ctx:Context<CloseUserBalanceAccount>, args: CloseUserBalanceAccountArgs) -> Result<()> {
+        instructions::close_user_balance_account(ctx, args)?;
+        Ok(())
+    }
+
+    pub fn settle_user_balance(ctx:Context<SettleUserBalance>, args: SettleUserBalanceArgs) -> Result<()> {
+        instructions::settle_user_balance(ctx, args)?;
+        Ok(())
+    }
+
+    /// Variant of settle_user_balance cranked by the market admin or the consume events authority, so the operators
+    /// can sweep the forgotten balances back to the users. The tokens can only go to the owner's associated token accounts
+    pub fn settle_to_owner(ctx:Context<SettleToOwner>) -> Result<()> {
+        instructions::settle_to_owner(ctx)?;
+        Ok(())
+    }
+
+    /// Lets the active traders keep the tokens in the user balance account, the deposited
+    /// balance can fund the orders placed with fund_from_balance, without any token transfers
+    pub fn deposit(ctx:Context<Deposit>, args: DepositArgs) -> Result<()> {
+        instructions::deposit(ctx, args)?;
         Ok(())
     }
 
-    pub fn settle_user_balance(ctx:Context<SettleUserBalance>) -> Result<()> {
-        instructions::settle_user_balance(ctx)?;
+    /// Withdraws the given amounts from the free balance of the user balance account
+    pub fn withdraw(ctx:Context<Withdraw>, args: WithdrawArgs) -> Result<()> {
+        instructions::withdraw(ctx, args)?;
         Ok(())
     }
 
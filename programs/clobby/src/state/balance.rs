@@ -8,27 +8,15 @@ use anchor_lang::prelude::*;
 pub struct UserBalance{
     pub market: Pubkey,
     pub user: Pubkey,
+    /// Index of the sub account, lets a user isolate the strategies under one wallet
+    pub sub_account: u16,
     pub base_token: Pubkey,
     pub quote_token: Pubkey,
     pub base_amount: u64,
     pub quote_amount: u64,
+    /// Base amount escrowed in the resting asks of the user, 
+    /// stays locked until their fill or out events are consumed
+    pub base_locked: u64,
+    /// Quote amount escrowed in the resting bids of the user (including the maker fees)
+    pub quote_locked: u64,
 }
-
-pub enum ResetSide{
-    Base,
-    Quote
-}
-
-impl UserBalance{
-    pub fn reset_balance(&mut self, side: ResetSide){
-        match side {
-            ResetSide::Base => {
-                self.base_amount = 0;
-            },
-            ResetSide::Quote => {
-                self.quote_amount = 0;
-            }
-        }
-    }
-    
-}
\ No newline at end of file
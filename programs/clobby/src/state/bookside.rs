@@ -2,12 +2,25 @@ use anchor_lang::prelude::*;
 
 use crate::errors::ClobbyProgramError;
 
+use super::Market;
+
 #[derive(AnchorDeserialize, AnchorSerialize, PartialEq, Eq, Clone, InitSpace, Copy, Debug)]
 pub enum Side{
     Bid, 
     Ask
 }
 
+impl Side {
+    /// Checks whether an order on this side at `price` can be matched
+    /// against an opposing order resting at `opposing_price`
+    pub fn crosses(&self, price: u64, opposing_price: u64) -> bool {
+        match self {
+            Side::Bid => opposing_price <= price,
+            Side::Ask => opposing_price >= price,
+        }
+    }
+}
+
 #[zero_copy]
 #[derive(
     PartialEq, Eq, Debug
@@ -15,8 +28,43 @@ pub enum Side{
 pub struct BookSideOrder{
     pub order_id: u64,
     pub base_amount: u64,
-    pub quote_amount: u64,
+    pub price_in_ticks: u64, // price of one base lot
     pub order_authority: Pubkey,
+    pub sub_account: u16, // sub account of the order_authority, that placed the order
+    pub padding: [u8; 6], // keeps the following fields aligned to 8 bytes
+    pub client_order_id: u64, // assigned by the user, to identify the order
+    pub expires_at_timestamp: i64, // 0 -> never expires
+    pub expires_at_slot: u64, // 0 -> never expires
+}
+
+impl BookSideOrder {
+    pub fn is_expired(&self, clock: &Clock) -> bool {
+        let timestamp_expired = self.expires_at_timestamp != 0 && clock.unix_timestamp >= self.expires_at_timestamp;
+        let slot_expired = self.expires_at_slot != 0 && clock.slot >= self.expires_at_slot;
+
+        timestamp_expired || slot_expired
+    }
+
+    /// Native quote amount of one base lot
+    pub fn quote_amount(&self, market: &Market) -> u64 {
+        market.ticks_to_quote_amount(self.price_in_ticks)
+    }
+
+    /// quote amount locked by the order, when it sits on the bids (including the maker fee)
+    pub fn total_quote_amount(&self, market: &Market) -> u64 {
+        market.bid_escrow(self.quote_amount(market), self.base_amount / market.base_lot_size)
+    }
+
+    pub fn remove(&mut self, market: Pubkey){
+        self.base_amount = 0;
+        self.order_id = 0; // this is important
+        self.price_in_ticks = 0;
+        self.order_authority = market;
+        self.sub_account = 0;
+        self.client_order_id = 0;
+        self.expires_at_timestamp = 0;
+        self.expires_at_slot = 0;
+    }
 }
 
 #[account(zero_copy)]
@@ -50,16 +98,31 @@ impl BookSide{
         
         match side {
             Side::Bid => {
-                self.orders[..=till_order_idx].sort_by(|a, b| b.quote_amount.cmp(&a.quote_amount));
+                self.orders[..=till_order_idx].sort_by_key(|order| std::cmp::Reverse(order.price_in_ticks));
             },
             Side::Ask => {
-                self.orders[..=till_order_idx].sort_by(|a, b| a.quote_amount.cmp(&b.quote_amount));
+                self.orders[..=till_order_idx].sort_by_key(|order| order.price_in_ticks);
             }
         }
 
         Ok(())
     }
 
+    /// Brings the valid orders to the front, while keeping their price-time order.
+    /// Should be called after removing orders from anywhere in the bookside
+    pub fn compact_orders(&mut self){
+        let mut valid_count = 0_usize;
+
+        for i in 0..self.order_count as usize {
+            if self.orders[i].order_id != 0 {
+                self.orders.swap(i, valid_count);
+                valid_count += 1;
+            }
+        }
+
+        self.order_count = valid_count as u64;
+    }
+
     pub fn reposition_orders_after_match(&self){
 
     }
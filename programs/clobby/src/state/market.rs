@@ -1,5 +1,9 @@
 use anchor_lang::prelude::*;
 
+use crate::errors::ClobbyProgramError;
+
+const BPS_DENOMINATOR: u128 = 10_000;
+
 #[account]
 #[derive(InitSpace)]
 pub struct Market{
@@ -9,10 +13,31 @@ pub struct Market{
     pub base_token: Pubkey, 
     pub quote_token: Pubkey,
     pub consume_events_authority: Pubkey,
+    /// Can sweep the accrued fees of the market
+    pub admin: Pubkey,
     /// Should be in the power of 10's, 
     /// if 1 base_lot_size = 1000, then buying 10 base lots, will be equal to
     /// 1_000_000 native base tokens in LAMPORTS    
     pub base_lot_size: u64, 
+    /// Native quote tokens in one quote lot
+    pub quote_lot_size: u64,
+    /// Number of quote lots in one tick, prices of the orders (for one base lot)
+    /// can only be in the multiples of tick_size * quote_lot_size
+    pub tick_size: u64,
+    /// Minimum base lots of an order
+    pub min_base_lots: u64,
+    /// Maximum base lots of an order, 0 -> no maximum
+    pub max_base_lots: u64,
+    /// Minimum native quote amount of a priced order, 0 -> no minimum
+    pub min_notional: u64,
+    /// Fee rate charged to the makers in basis points, negative for rebates
+    pub maker_fee_bps: i16,
+    /// Fee rate charged to the takers in basis points
+    pub taker_fee_bps: i16,
+    /// Share of the protocol's taker fee credited to the referrer of the taker, in basis points
+    pub referrer_share_bps: u16,
+    /// Native quote fees accrued by the protocol, that are not swept yet
+    pub fees_accrued: u64,
     pub total_orders: u64,
     pub bids: Pubkey,
     pub asks: Pubkey,
@@ -20,4 +45,72 @@ pub struct Market{
     pub quote_token_vault: Pubkey,
     #[max_len(15)]
     pub name: String,  // always better to use at last
-}
\ No newline at end of file
+}
+
+impl Market {
+
+    /// Native quote amount of one tick, for one base lot
+    pub fn tick_value(&self) -> u64 {
+        self.tick_size * self.quote_lot_size
+    }
+
+    /// Converts the quote amount of one base lot into ticks, fails for off tick prices
+    // is_multiple_of is not available yet on the solana toolchain
+    #[allow(clippy::manual_is_multiple_of)]
+    pub fn quote_amount_to_ticks(&self, quote_amount: u64) -> Result<u64> {
+        require!(quote_amount % self.tick_value() == 0, ClobbyProgramError::OffTickPrice);
+        Ok(quote_amount / self.tick_value())
+    }
+
+    pub fn ticks_to_quote_amount(&self, price_in_ticks: u64) -> u64 {
+        price_in_ticks * self.tick_value()
+    }
+
+    /// Checks the order size against the limits of the market,
+    /// `notional` is the total quote amount of the order, None for orders without a price
+    pub fn validate_order_size(&self, base_lots: u64, notional: Option<u64>) -> Result<()> {
+        require_gte!(base_lots, self.min_base_lots, ClobbyProgramError::OrderSizeTooSmall);
+
+        if self.max_base_lots != 0 {
+            require_gte!(self.max_base_lots, base_lots, ClobbyProgramError::OrderSizeTooLarge);
+        }
+
+        if let Some(notional) = notional {
+            require_gte!(notional, self.min_notional, ClobbyProgramError::OrderNotionalTooSmall);
+        }
+
+        Ok(())
+    }
+
+    /// Fee paid by a taker for matching `quote_amount`, rounded up in favour of the protocol
+    pub fn taker_fee(&self, quote_amount: u64) -> u64 {
+        let fee_bps = self.taker_fee_bps.max(0) as u128;
+        (quote_amount as u128 * fee_bps).div_ceil(BPS_DENOMINATOR) as u64
+    }
+
+    /// Fee paid by a maker for a fill of `base_lots` at `quote_amount` per base lot.
+    /// It is rounded down per base lot, so the fees of the partial fills always 
+    /// add up to the escrowed fee of the order
+    pub fn maker_fee(&self, quote_amount: u64, base_lots: u64) -> u64 {
+        let fee_bps = self.maker_fee_bps.max(0) as u128;
+        (quote_amount as u128 * fee_bps / BPS_DENOMINATOR) as u64 * base_lots
+    }
+
+    /// Rebate paid to a maker for a fill of `base_lots` at `quote_amount` per base lot,
+    /// rounded down per base lot
+    pub fn maker_rebate(&self, quote_amount: u64, base_lots: u64) -> u64 {
+        let rebate_bps = self.maker_fee_bps.min(0).unsigned_abs() as u128;
+        (quote_amount as u128 * rebate_bps / BPS_DENOMINATOR) as u64 * base_lots
+    }
+
+    /// Part of the protocol's `fee` credited to the referrer, rounded down
+    pub fn referrer_share(&self, fee: u64) -> u64 {
+        (fee as u128 * self.referrer_share_bps as u128 / BPS_DENOMINATOR) as u64
+    }
+
+    /// Quote amount escrowed by a resting bid of `base_lots` at `quote_amount` per base lot,
+    /// bids escrow their maker fee as they receive base tokens on a fill
+    pub fn bid_escrow(&self, quote_amount: u64, base_lots: u64) -> u64 {
+        quote_amount * base_lots + self.maker_fee(quote_amount, base_lots)
+    }
+}
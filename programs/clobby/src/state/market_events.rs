@@ -17,14 +17,18 @@ pub struct Event{
     pub base_amount: u64,
     pub quote_amount: u64,
     pub maker: Pubkey,
+    pub maker_sub_account: u16,
+    pub padding: [u8; 6], // keeps the following fields aligned to 8 bytes
     pub side: u64, // 0 -> Bid, 1 -> Ask
     pub event_type : u64, // 0 -> Fill, 1 -> Out
 }
 
+/// Circular queue of the events, the oldest event sits at `head`
 #[account(zero_copy)]
 pub struct MarketEvents{
     pub market: Pubkey,
-    pub events_to_process: u64,
+    pub head: u64, // index of the oldest event
+    pub count: u64, // number of events waiting to be consumed
     pub total_events_count: u64,
     pub events: [Event; 512],
 }
@@ -33,6 +37,7 @@ pub struct MarketEvents{
 pub struct EventParams{
     pub order_id: u64,
     pub maker: Pubkey,
+    pub maker_sub_account: u16,
     pub side: Side,
     pub event_type: EventType,
     pub base_amount: u64,
@@ -73,6 +78,7 @@ impl Event {
         self.base_amount =  0;
         self.quote_amount = 0;
         self.maker = market;
+        self.maker_sub_account = 0;
         self.order_id = 0;
         self.event_type = 0;
         self.side = 0;
@@ -82,9 +88,10 @@ impl Event {
 
 impl MarketEvents {
     
+    /// Pushes the event at the back of the queue
     pub fn add_event(&mut self, event:EventParams) {
         
-        let index = self.events_to_process as usize;
+        let index = (self.head + self.count) as usize % self.events.len();
 
         let event_type :u64 = match event.event_type {
             EventType::Fill => 0,
@@ -102,20 +109,124 @@ impl MarketEvents {
             base_amount: event.base_amount,
             quote_amount: event.quote_amount,
             maker: event.maker,
+            maker_sub_account: event.maker_sub_account,
+            padding: [0; 6],
             order_id: event.order_id,
             id: event_id,
             event_type,
             side: order_side,
         };
 
-        self.events_to_process+=1;
+        self.count+=1;
         self.total_events_count+=1;
     }
 
     pub fn can_add_event(&self, events_to_add:usize) -> bool{
-        // we add 6 as an extra buffer
-        events_to_add + 6 < self.events.len()
+        self.count as usize + events_to_add <= self.events.len()
+    }
+
+    /// Returns the oldest event of the queue
+    pub fn front(&self) -> Option<Event> {
+        if self.count == 0 {
+            return None;
+        }
+
+        Some(self.events[self.head as usize])
+    }
+
+    /// Returns the event at `offset` from the front of the queue
+    pub fn get(&self, offset: usize) -> Option<Event> {
+        if offset >= self.count as usize {
+            return None;
+        }
+
+        Some(self.events[self.index_of(offset)])
+    }
+
+    /// Index in `events` of the event at `offset` from the front of the queue
+    pub fn index_of(&self, offset: usize) -> usize {
+        (self.head as usize + offset) % self.events.len()
+    }
+
+    /// Removes the oldest event of the queue
+    pub fn pop_front(&mut self, market:Pubkey) {
+        if self.count == 0 {
+            return;
+        }
+
+        let head = self.head as usize;
+        self.events[head].remove(market);
+
+        self.head = ((head + 1) % self.events.len()) as u64;
+        self.count-=1;
+    }
+
+}
 
+#[cfg(test)]
+mod tests {
+    use bytemuck::Zeroable;
+
+    use super::*;
+
+    fn fill_event(order_id: u64) -> EventParams {
+        EventParams {
+            order_id,
+            maker: Pubkey::default(),
+            maker_sub_account: 0,
+            side: Side::Bid,
+            event_type: EventType::Fill,
+            base_amount: 1000,
+            quote_amount: 1000,
+        }
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn wraps_around_the_end_of_the_events() {
+        let mut market_events = MarketEvents::zeroed();
+        let capacity = market_events.events.len();
+
+        for order_id in 1..=capacity as u64 {
+            assert!(market_events.can_add_event(1));
+            market_events.add_event(fill_event(order_id));
+        }
+
+        assert!(!market_events.can_add_event(1));
+        assert!(market_events.can_add_event(0));
+
+        // consume most of the queue, so the next events go past the end of the array
+        let consumed = capacity - 12;
+
+        for order_id in 1..=consumed as u64 {
+            assert_eq!(market_events.front().unwrap().order_id, order_id);
+            market_events.pop_front(Pubkey::default());
+        }
+
+        assert_eq!(market_events.head as usize, consumed);
+        assert!(market_events.can_add_event(consumed));
+        assert!(!market_events.can_add_event(consumed + 1));
+
+        let added = 100;
+
+        for order_id in capacity as u64 + 1..=(capacity + added) as u64 {
+            market_events.add_event(fill_event(order_id));
+        }
+
+        assert_eq!(market_events.count as usize, 12 + added);
+        assert_eq!(market_events.index_of(11), capacity - 1);
+        assert_eq!(market_events.index_of(12), 0);
+        assert_eq!(market_events.events[0].order_id, capacity as u64 + 1);
+        assert_eq!(market_events.get(12).unwrap().order_id, capacity as u64 + 1);
+        assert!(market_events.get(12 + added).is_none());
+
+        // the events come out in the order they were added, across the end of the array
+        for order_id in consumed as u64 + 1..=(capacity + added) as u64 {
+            assert_eq!(market_events.front().unwrap().order_id, order_id);
+            market_events.pop_front(Pubkey::default());
+        }
+
+        assert!(market_events.front().is_none());
+        assert_eq!(market_events.head as usize, added);
+        assert_eq!(market_events.total_events_count as usize, capacity + added);
+    }
+}
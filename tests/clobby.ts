@@ -18,6 +18,19 @@ describe("clobby", () => {
 
   const MAX_ACCOUNT_SPACE = 10_485_760; 
 
+  const QUOTE_LOT_SIZE = 1;
+  const TICK_SIZE = 5;
+  // native quote amount of one tick, for one base lot
+  const TICK_VALUE = QUOTE_LOT_SIZE * TICK_SIZE;
+  const MIN_BASE_LOTS = 1;
+  const MAX_BASE_LOTS = 100;
+  const MIN_NOTIONAL = TICK_VALUE * 10;
+  // fees are kept at zero, so that the balances below stay easy to follow,
+  // they are tested on separate markets at the end
+  const MAKER_FEE_BPS = 0;
+  const TAKER_FEE_BPS = 0;
+  const REFERRER_SHARE_BPS = 2_000;
+
   const bidAccount = anchor.web3.Keypair.generate();
   const askAccount = anchor.web3.Keypair.generate();
   const marketEvent = anchor.web3.Keypair.generate();
@@ -36,11 +49,21 @@ describe("clobby", () => {
     return result;
   }
 
-  const getBalanceAccount = (userKey: anchor.web3.PublicKey) => {
+  // events are stored in a circular queue, starting at the head
+  const getEvent = (marketEventAcc: {head: anchor.BN, events: any[]}, index: number) => {
+    return marketEventAcc.events[(marketEventAcc.head.toNumber() + index) % marketEventAcc.events.length];
+  }
+
+  const getBalanceAccount = (userKey: anchor.web3.PublicKey, subAccount = 0, marketKey = market.publicKey) => {
+    const subAccountSeed = Buffer.alloc(2);
+    subAccountSeed.writeUInt16LE(subAccount);
+
     const [balanceAccount] = anchor.web3.PublicKey.findProgramAddressSync(
       [
         Buffer.from("balance"),
-        userKey.toBuffer()
+        marketKey.toBuffer(),
+        userKey.toBuffer(),
+        subAccountSeed,
       ],
       PROGRAM_ID,
     );
@@ -232,6 +255,14 @@ describe("clobby", () => {
     .createMarket({
       name:"SOL_USDC",
       baseLotSize: new anchor.BN(1000),
+      quoteLotSize: new anchor.BN(QUOTE_LOT_SIZE),
+      tickSize: new anchor.BN(TICK_SIZE),
+      minBaseLots: new anchor.BN(MIN_BASE_LOTS),
+      maxBaseLots: new anchor.BN(MAX_BASE_LOTS),
+      minNotional: new anchor.BN(MIN_NOTIONAL),
+      makerFeeBps: MAKER_FEE_BPS,
+      takerFeeBps: TAKER_FEE_BPS,
+      referrerShareBps: REFERRER_SHARE_BPS,
       consumeEventsAuthority: keypair.publicKey,
     })
     .accounts({
@@ -268,6 +299,16 @@ describe("clobby", () => {
     expect(marketAcc.quoteTokenVault.toBase58()).to.equal(quoteTokenVault.toBase58());
     expect(marketAcc.name).to.equal("SOL_USDC");
     expect(marketAcc.baseLotSize.toNumber()).to.equal(1000);
+    expect(marketAcc.quoteLotSize.toNumber()).to.equal(QUOTE_LOT_SIZE);
+    expect(marketAcc.tickSize.toNumber()).to.equal(TICK_SIZE);
+    expect(marketAcc.minBaseLots.toNumber()).to.equal(MIN_BASE_LOTS);
+    expect(marketAcc.maxBaseLots.toNumber()).to.equal(MAX_BASE_LOTS);
+    expect(marketAcc.minNotional.toNumber()).to.equal(MIN_NOTIONAL);
+    expect(marketAcc.makerFeeBps).to.equal(MAKER_FEE_BPS);
+    expect(marketAcc.takerFeeBps).to.equal(TAKER_FEE_BPS);
+    expect(marketAcc.referrerShareBps).to.equal(REFERRER_SHARE_BPS);
+    expect(marketAcc.feesAccrued.toNumber()).to.equal(0);
+    expect(marketAcc.admin.toBase58()).to.equal(keypair.publicKey.toBase58());
     expect(marketAcc.marketAuthority.toBase58()).to.equal(marketAuthority.toBase58());
     expect(marketAcc.marketEvents.toBase58()).to.equal(marketEvent.publicKey.toBase58());
     expect(marketAcc.marketAuthorityBump).to.equal(marketAuthorityBump);
@@ -282,7 +323,8 @@ describe("clobby", () => {
     expect(marketAuthorityAcc.value.owner.toBase58()).to.equal(PROGRAM_ID.toBase58());
     expect(marketAuthorityAcc.value.lamports).to.gt(0);
     expect(marketEventAcc.market.toBase58()).to.equal(market.publicKey.toBase58());
-    expect(marketEventAcc.eventsToProcess.toNumber()).to.equal(0);
+    expect(marketEventAcc.head.toNumber()).to.equal(0);
+    expect(marketEventAcc.count.toNumber()).to.equal(0);
     
     // create_bookside_accounts tests
     const asksAcc = await program.account.bookSide.fetch(askAccount.publicKey);
@@ -306,7 +348,9 @@ describe("clobby", () => {
     const userBalanceAccount = getBalanceAccount(keypair.publicKey);
 
     await program.methods
-    .createUserBalanceAccount()
+    .createUserBalanceAccount({
+      subAccount: 0,
+    })
     .accounts({
       user: keypair.publicKey.toBase58(),
       market: market.publicKey.toBase58(),
@@ -317,7 +361,10 @@ describe("clobby", () => {
 
     expect(balanceAccount.baseAmount.toNumber()).to.equal(0);
     expect(balanceAccount.quoteAmount.toNumber()).to.equal(0);
+    expect(balanceAccount.baseLocked.toNumber()).to.equal(0);
+    expect(balanceAccount.quoteLocked.toNumber()).to.equal(0);
     expect(balanceAccount.user.toBase58()).to.equal(keypair.publicKey.toBase58());
+    expect(balanceAccount.subAccount).to.equal(0);
     expect(balanceAccount.market.toBase58()).to.equal(market.publicKey.toBase58());
     expect(balanceAccount.baseToken.toBase58()).to.equal(baseToken.publicKey.toBase58());
     expect(balanceAccount.quoteToken.toBase58()).to.equal(quoteToken.publicKey.toBase58());
@@ -341,7 +388,12 @@ describe("clobby", () => {
     await program.methods
     .placeOrder({
       baseLots: 2, // Buy two base lots
-      ioc: false, // Non-immediate-or-cancel order
+      orderType: {limit:{}},
+      expiry: null,
+      selfTradeBehavior: {allow:{}},
+      clientOrderId: new anchor.BN(0),
+      subAccount: 0,
+      fundFromBalance: false,
       quoteAmount: new anchor.BN(1000), // Buy 1000 quote tokens
       side: {bid:{}},
     })
@@ -354,6 +406,7 @@ describe("clobby", () => {
       bids: bidAccount.publicKey.toBase58(),
       asks: askAccount.publicKey.toBase58(),   
       marketEvents: marketEvent.publicKey.toBase58(),
+      referrerBalanceAccount: null,
       tokenProgram: TOKEN_2022_PROGRAM_ID,
     })
     .rpc({commitment: "confirmed"});
@@ -378,8 +431,13 @@ describe("clobby", () => {
     expect(bidsAcc.orderCount.toNumber()).to.equal(1);
     expect(bidsAcc.orders[0].orderId.toNumber()).to.equal(1);
     expect(bidsAcc.orders[0].baseAmount.toNumber()).to.equal(marketAcc.baseLotSize.toNumber() * 2);
-    expect(bidsAcc.orders[0].quoteAmount.toNumber()).to.equal(1000);
+    expect(bidsAcc.orders[0].priceInTicks.toNumber()).to.equal(1000 / TICK_VALUE);
     expect(bidsAcc.orders[0].orderAuthority.toBase58()).to.equal(keypair.publicKey.toBase58());
+
+    // the escrow of the resting bid is locked in the balance account
+    const balanceAcc = await program.account.userBalance.fetch(userBalanceAccount);
+    expect(balanceAcc.quoteLocked.toNumber()).to.equal(Number(transferAmount));
+    expect(balanceAcc.baseLocked.toNumber()).to.equal(0);
   });
 
   it("Should match completely!", async() => {
@@ -406,7 +464,12 @@ describe("clobby", () => {
     const ix1 = await program.methods
     .placeOrder({
       baseLots: 2, // Buy two base lots
-      ioc: false, // Non-immediate-or-cancel order
+      orderType: {limit:{}},
+      expiry: null,
+      selfTradeBehavior: {allow:{}},
+      clientOrderId: new anchor.BN(0),
+      subAccount: 0,
+      fundFromBalance: false,
       quoteAmount: new anchor.BN(1000), // Buy 1000 quote tokens
       side: {bid:{}},
     })
@@ -419,6 +482,7 @@ describe("clobby", () => {
       bids: bidAccount.publicKey.toBase58(),
       asks: askAccount.publicKey.toBase58(),   
       marketEvents: marketEvent.publicKey.toBase58(),
+      referrerBalanceAccount: null,
       tokenProgram: TOKEN_2022_PROGRAM_ID,
     })
     .instruction();
@@ -426,7 +490,12 @@ describe("clobby", () => {
     const ix2 = await program.methods
     .placeOrder({
       baseLots: 2, // Sell two base lots
-      ioc: false, // Non-immediate-or-cancel order
+      orderType: {limit:{}},
+      expiry: null,
+      selfTradeBehavior: {allow:{}},
+      clientOrderId: new anchor.BN(0),
+      subAccount: 0,
+      fundFromBalance: false,
       quoteAmount: new anchor.BN(1000), // Sell each at the price 1000 quote tokens
       side: {ask:{}},
     })
@@ -439,6 +508,7 @@ describe("clobby", () => {
       bids: bidAccount.publicKey.toBase58(),
       asks: askAccount.publicKey.toBase58(),   
       marketEvents: marketEvent.publicKey.toBase58(),
+      referrerBalanceAccount: null,
       tokenProgram: TOKEN_2022_PROGRAM_ID,
     })
     .instruction();
@@ -472,14 +542,14 @@ describe("clobby", () => {
     expect(userBalanceAcc.quoteAmount.toNumber()).to.be.equal(1000*2);
 
     expect(bidsAcc.orders[0].orderId.toNumber()).to.be.equal(2);
-    expect(bidsAcc.orders[0].quoteAmount.toNumber()).to.be.equal(1000);
+    expect(bidsAcc.orders[0].priceInTicks.toNumber()).to.be.equal(1000 / TICK_VALUE);
     expect(bidsAcc.orders[0].baseAmount.toNumber()).to.be.equal(marketAcc.baseLotSize.toNumber() * 2);
 
-    expect(asksAcc.orders[0].quoteAmount.toNumber()).to.be.equal(0);
+    expect(asksAcc.orders[0].priceInTicks.toNumber()).to.be.equal(0);
     expect(asksAcc.orders[0].baseAmount.toNumber()).to.be.equal(0);
     expect(asksAcc.orders[0].orderId.toNumber()).to.be.equal(0);
 
-    expect(marketEventAcc.eventsToProcess.toNumber()).to.be.equal(1);
+    expect(marketEventAcc.count.toNumber()).to.be.equal(1);
     expect(marketEventAcc.totalEventsCount.toNumber()).to.be.equal(1);
     expect(marketEventAcc.events[0].orderId.toNumber()).to.be.equal(1);
     expect(marketEventAcc.events[0].side.toNumber()).to.be.equal(0);
@@ -503,7 +573,12 @@ describe("clobby", () => {
     const ix1 = await program.methods
     .placeOrder({
       baseLots: 2, // Buy two base lots
-      ioc: false, // Non-immediate-or-cancel order
+      orderType: {limit:{}},
+      expiry: null,
+      selfTradeBehavior: {allow:{}},
+      clientOrderId: new anchor.BN(0),
+      subAccount: 0,
+      fundFromBalance: false,
       quoteAmount: new anchor.BN(1000), // Buy 1000 quote tokens
       side: {bid:{}},
     })
@@ -516,6 +591,7 @@ describe("clobby", () => {
       bids: bidAccount.publicKey.toBase58(),
       asks: askAccount.publicKey.toBase58(),   
       marketEvents: marketEvent.publicKey.toBase58(),
+      referrerBalanceAccount: null,
       tokenProgram: TOKEN_2022_PROGRAM_ID,
     })
     .instruction();
@@ -523,7 +599,12 @@ describe("clobby", () => {
     const ix2 = await program.methods
     .placeOrder({
       baseLots: 5, // Sell 5 base lots
-      ioc: false, // Non-immediate-or-cancel order
+      orderType: {limit:{}},
+      expiry: null,
+      selfTradeBehavior: {allow:{}},
+      clientOrderId: new anchor.BN(0),
+      subAccount: 0,
+      fundFromBalance: false,
       quoteAmount: new anchor.BN(1000), // Sell each at the price 1000 quote tokens
       side: {ask:{}},
     })
@@ -536,6 +617,7 @@ describe("clobby", () => {
       bids: bidAccount.publicKey.toBase58(),
       asks: askAccount.publicKey.toBase58(),   
       marketEvents: marketEvent.publicKey.toBase58(),
+      referrerBalanceAccount: null,
       tokenProgram: TOKEN_2022_PROGRAM_ID,
     })
     .instruction();
@@ -572,14 +654,14 @@ describe("clobby", () => {
     expect(userBalanceAccAfter.quoteAmount.toNumber()).to.be.equal(userBalanceAccBefore.quoteAmount.toNumber() + 1000*4);
 
     expect(bidsAcc.orders[0].orderId.toNumber()).to.be.equal(0);
-    expect(bidsAcc.orders[0].quoteAmount.toNumber()).to.be.equal(0);
+    expect(bidsAcc.orders[0].priceInTicks.toNumber()).to.be.equal(0);
     expect(bidsAcc.orders[0].baseAmount.toNumber()).to.be.equal(0);
 
     expect(asksAcc.orders[0].orderId.toNumber()).to.be.equal(5);
-    expect(asksAcc.orders[0].quoteAmount.toNumber()).to.be.equal(1000);
+    expect(asksAcc.orders[0].priceInTicks.toNumber()).to.be.equal(1000 / TICK_VALUE);
     expect(asksAcc.orders[0].baseAmount.toNumber()).to.be.equal(1000);
 
-    expect(marketEventAcc.eventsToProcess.toNumber()).to.be.equal(3);
+    expect(marketEventAcc.count.toNumber()).to.be.equal(3);
     expect(marketEventAcc.totalEventsCount.toNumber()).to.be.equal(3);
 
     expect(marketEventAcc.events[1].orderId.toNumber()).to.be.equal(2);
@@ -596,12 +678,56 @@ describe("clobby", () => {
 
   })
 
-  it("Should be able to cancel an IOC order", async() => {
+  it("Should not place the unfilled remaining of an IOC order", async() => {
+
+    const userQuoteTokenAccountBefore = await getAccount(connection, userQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+    const bidsBefore = await program.account.bookSide.fetch(bidAccount.publicKey);
+
+    await program.methods
+    .placeOrder({
+      baseLots: 5,
+      orderType: {immediateOrCancel:{}},
+      expiry: null,
+      selfTradeBehavior: {allow:{}},
+      clientOrderId: new anchor.BN(0),
+      subAccount: 0,
+      fundFromBalance: false,
+      quoteAmount: new anchor.BN(500), // does not cross the ask resting at 1000
+      side: {bid:{}}
+    })
+    .accounts({
+      user: keypair.publicKey.toBase58(),
+      userTokenAccount: userQuoteTokenAccount.toBase58(),
+      market: market.publicKey.toBase58(),
+      tokenToTrade: quoteToken.publicKey.toBase58(),
+      tokenVault: quoteTokenVault.toBase58(),
+      bids: bidAccount.publicKey.toBase58(),
+      asks: askAccount.publicKey.toBase58(),   
+      marketEvents: marketEvent.publicKey.toBase58(),
+      referrerBalanceAccount: null,
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
+    })
+    .rpc({commitment: "confirmed"});
+
+    const userQuoteTokenAccountAfter = await getAccount(connection, userQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+    const bidsAfter = await program.account.bookSide.fetch(bidAccount.publicKey);
+
+    // nothing got matched, so nothing should be transferred or placed
+    expect(userQuoteTokenAccountAfter.amount).to.equal(userQuoteTokenAccountBefore.amount);
+    expect(bidsAfter.orderCount.toNumber()).to.equal(bidsBefore.orderCount.toNumber());
+  })
+
+  it("Should be able to cancel a FOK order", async() => {
     try {
       await program.methods
       .placeOrder({
         baseLots: 5, // Buy two base lots
-        ioc: true, 
+        orderType: {fillOrKill:{}},
+        expiry: null,
+        selfTradeBehavior: {allow:{}},
+        clientOrderId: new anchor.BN(0),
+        subAccount: 0,
+        fundFromBalance: false,
         quoteAmount: new anchor.BN(500),
         side: {bid:{}}// Buy 1000 quote tokens
       })
@@ -614,6 +740,7 @@ describe("clobby", () => {
         bids: bidAccount.publicKey.toBase58(),
         asks: askAccount.publicKey.toBase58(),   
         marketEvents: marketEvent.publicKey.toBase58(),
+        referrerBalanceAccount: null,
         tokenProgram: TOKEN_2022_PROGRAM_ID,
       })
       .rpc({commitment: "confirmed"});
@@ -629,6 +756,213 @@ describe("clobby", () => {
       
   })
 
+  it("Should reject a crossing post only order", async() => {
+    try {
+      await program.methods
+      .placeOrder({
+        baseLots: 1,
+        orderType: {postOnly:{}},
+        expiry: null,
+        selfTradeBehavior: {allow:{}},
+        clientOrderId: new anchor.BN(0),
+        subAccount: 0,
+        fundFromBalance: false,
+        quoteAmount: new anchor.BN(1000), // crosses the ask resting at 1000
+        side: {bid:{}}
+      })
+      .accounts({
+        user: keypair.publicKey.toBase58(),
+        userTokenAccount: userQuoteTokenAccount.toBase58(),
+        market: market.publicKey.toBase58(),
+        tokenToTrade: quoteToken.publicKey.toBase58(),
+        tokenVault: quoteTokenVault.toBase58(),
+        bids: bidAccount.publicKey.toBase58(),
+        asks: askAccount.publicKey.toBase58(),   
+        marketEvents: marketEvent.publicKey.toBase58(),
+        referrerBalanceAccount: null,
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .rpc({commitment: "confirmed"});
+
+      throw new Error("Post only order should not have been placed");
+    } catch (error) {
+      if(error instanceof anchor.AnchorError){
+        expect(error.error.errorCode.code).to.be.equal("PostOnlyWouldCross");
+        expect(error.error.errorCode.number).to.be.equal(6006);
+      }
+      else{
+        throw error;
+      }
+    }
+  })
+
+  it("Should reject an order that has already expired", async() => {
+    try {
+      await program.methods
+      .placeOrder({
+        baseLots: 1,
+        orderType: {limit:{}},
+        expiry: {slot:{slot: new anchor.BN(1)}},
+        selfTradeBehavior: {allow:{}},
+        clientOrderId: new anchor.BN(0),
+        subAccount: 0,
+        fundFromBalance: false,
+        quoteAmount: new anchor.BN(500),
+        side: {bid:{}}
+      })
+      .accounts({
+        user: keypair.publicKey.toBase58(),
+        userTokenAccount: userQuoteTokenAccount.toBase58(),
+        market: market.publicKey.toBase58(),
+        tokenToTrade: quoteToken.publicKey.toBase58(),
+        tokenVault: quoteTokenVault.toBase58(),
+        bids: bidAccount.publicKey.toBase58(),
+        asks: askAccount.publicKey.toBase58(),   
+        marketEvents: marketEvent.publicKey.toBase58(),
+        referrerBalanceAccount: null,
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .rpc({commitment: "confirmed"});
+
+      throw new Error("Expired order should not have been placed");
+    } catch (error) {
+      if(error instanceof anchor.AnchorError){
+        expect(error.error.errorCode.code).to.be.equal("OrderAlreadyExpired");
+        expect(error.error.errorCode.number).to.be.equal(6008);
+      }
+      else{
+        throw error;
+      }
+    }
+  })
+
+  it("Should abort a self trade", async() => {
+    try {
+      await program.methods
+      .placeOrder({
+        baseLots: 1,
+        orderType: {limit:{}},
+        expiry: null,
+        selfTradeBehavior: {abortTransaction:{}},
+        clientOrderId: new anchor.BN(0),
+        subAccount: 0,
+        fundFromBalance: false,
+        quoteAmount: new anchor.BN(1000), // crosses the own ask resting at 1000
+        side: {bid:{}}
+      })
+      .accounts({
+        user: keypair.publicKey.toBase58(),
+        userTokenAccount: userQuoteTokenAccount.toBase58(),
+        market: market.publicKey.toBase58(),
+        tokenToTrade: quoteToken.publicKey.toBase58(),
+        tokenVault: quoteTokenVault.toBase58(),
+        bids: bidAccount.publicKey.toBase58(),
+        asks: askAccount.publicKey.toBase58(),   
+        marketEvents: marketEvent.publicKey.toBase58(),
+        referrerBalanceAccount: null,
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .rpc({commitment: "confirmed"});
+
+      throw new Error("Self trade should have been aborted");
+    } catch (error) {
+      if(error instanceof anchor.AnchorError){
+        expect(error.error.errorCode.code).to.be.equal("SelfTradeNotAllowed");
+        expect(error.error.errorCode.number).to.be.equal(6009);
+      }
+      else{
+        throw error;
+      }
+    }
+  })
+
+  it("Should reject an off tick price", async() => {
+    try {
+      await program.methods
+      .placeOrder({
+        baseLots: 1,
+        orderType: {limit:{}},
+        expiry: null,
+        selfTradeBehavior: {allow:{}},
+        clientOrderId: new anchor.BN(0),
+        subAccount: 0,
+        fundFromBalance: false,
+        quoteAmount: new anchor.BN(TICK_VALUE * 100 + 1),
+        side: {bid:{}}
+      })
+      .accounts({
+        user: keypair.publicKey.toBase58(),
+        userTokenAccount: userQuoteTokenAccount.toBase58(),
+        market: market.publicKey.toBase58(),
+        tokenToTrade: quoteToken.publicKey.toBase58(),
+        tokenVault: quoteTokenVault.toBase58(),
+        bids: bidAccount.publicKey.toBase58(),
+        asks: askAccount.publicKey.toBase58(),   
+        marketEvents: marketEvent.publicKey.toBase58(),
+        referrerBalanceAccount: null,
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .rpc({commitment: "confirmed"});
+
+      throw new Error("Off tick order should not have been placed");
+    } catch (error) {
+      if(error instanceof anchor.AnchorError){
+        expect(error.error.errorCode.code).to.be.equal("OffTickPrice");
+        expect(error.error.errorCode.number).to.be.equal(6011);
+      }
+      else{
+        throw error;
+      }
+    }
+  })
+
+  it("Should reject orders outside the size limits of the market", async() => {
+    const cases = [
+      {baseLots: MAX_BASE_LOTS + 1, quoteAmount: TICK_VALUE * 20, code: "OrderSizeTooLarge", number: 6013},
+      {baseLots: 1, quoteAmount: MIN_NOTIONAL - TICK_VALUE, code: "OrderNotionalTooSmall", number: 6014},
+    ];
+
+    for (const testCase of cases) {
+      try {
+        await program.methods
+        .placeOrder({
+          baseLots: testCase.baseLots,
+          orderType: {limit:{}},
+          expiry: null,
+          selfTradeBehavior: {allow:{}},
+          clientOrderId: new anchor.BN(0),
+          subAccount: 0,
+          fundFromBalance: false,
+          quoteAmount: new anchor.BN(testCase.quoteAmount),
+          side: {bid:{}}
+        })
+        .accounts({
+          user: keypair.publicKey.toBase58(),
+          userTokenAccount: userQuoteTokenAccount.toBase58(),
+          market: market.publicKey.toBase58(),
+          tokenToTrade: quoteToken.publicKey.toBase58(),
+          tokenVault: quoteTokenVault.toBase58(),
+          bids: bidAccount.publicKey.toBase58(),
+          asks: askAccount.publicKey.toBase58(),   
+          marketEvents: marketEvent.publicKey.toBase58(),
+          referrerBalanceAccount: null,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+        })
+        .rpc({commitment: "confirmed"});
+
+        throw new Error("Order outside the size limits should not have been placed");
+      } catch (error) {
+        if(error instanceof anchor.AnchorError){
+          expect(error.error.errorCode.code).to.be.equal(testCase.code);
+          expect(error.error.errorCode.number).to.be.equal(testCase.number);
+        }
+        else{
+          throw error;
+        }
+      }
+    }
+  })
+
   it("Should be able to cancel an order !", async() => {
 
     // as the asks will be sitting on the orderbook already
@@ -647,6 +981,7 @@ describe("clobby", () => {
     .cancelOrder({
       orderId,
       side: {ask:{}},
+      subAccount: 0,
     })
     .accounts({
       user: keypair.publicKey.toBase58(),
@@ -663,9 +998,9 @@ describe("clobby", () => {
     expect(asksAccountAfter.orderCount.toNumber()).to.equal(0);
     expect(asksAccountAfter.orders[0].orderId.toNumber()).to.equal(0);
     expect(asksAccountAfter.orders[0].baseAmount.toNumber()).to.equal(0);
-    expect(asksAccountAfter.orders[0].quoteAmount.toNumber()).to.equal(0);
+    expect(asksAccountAfter.orders[0].priceInTicks.toNumber()).to.equal(0);
 
-    expect(marketEventAcc.eventsToProcess.toNumber()).to.equal(4);
+    expect(marketEventAcc.count.toNumber()).to.equal(4);
     expect(marketEventAcc.totalEventsCount.toNumber()).to.equal(4);
 
     expect(marketEventAcc.events[3].orderId.toNumber()).to.be.equal(5);
@@ -677,103 +1012,1055 @@ describe("clobby", () => {
   })
 
 
-  it("Should be able to consume events", async() => {
-
-    const balanceBefore = await program
-    .account
-    .userBalance
-    .fetch(userBalanceAccount);
-
-    const eventsBefore = await program.account.marketEvents.fetch(marketEvent.publicKey);
-    let expectedBaseBalanceAmount = balanceBefore.baseAmount.toNumber();
-    let expectedQuoteBalanceAmount = balanceBefore.quoteAmount.toNumber();
-
-    const remainingAccounts : {
-      pubkey:anchor.web3.PublicKey,
-      isSigner: boolean,
-      isWritable: boolean
-    }[] = [];
-
-    for(let i = 0; i < eventsBefore.eventsToProcess.toNumber(); i++) {
-      let event = eventsBefore.events[i];
-
-    
-      if(event.side.toNumber() === 0){
-        // if the side is bid
-        if(event.eventType.toNumber() === 0){
-          // if it's a fill event
-          expectedBaseBalanceAmount += event.baseAmount.toNumber();
-        }
-        else{
-          // if its an out event
-          expectedQuoteBalanceAmount += event.quoteAmount.toNumber();
-        }
+  it("Should match a market bid within its quote limit", async() => {
 
-      }
-      else{
-        // if the side is ask
-        if(event.eventType.toNumber() === 0){
-          // if it's a fill event
-          expectedQuoteBalanceAmount += event.quoteAmount.toNumber();
-        }
-        else{
-          expectedBaseBalanceAmount += event.baseAmount.toNumber();
-        }
-      }
+    const userQuoteTokenAccountBefore = await getAccount(connection, userQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+    const userBalanceAccBefore = await program.account.userBalance.fetch(userBalanceAccount);
 
-      const balanceAccount = getBalanceAccount(event.maker);
-      remainingAccounts.push({
-        pubkey: balanceAccount,
-        isSigner: false,
-        isWritable: true
-      });
-    }
+    const ix1 = await program.methods
+    .placeOrder({
+      baseLots: 2,
+      orderType: {limit:{}},
+      expiry: null,
+      selfTradeBehavior: {allow:{}},
+      clientOrderId: new anchor.BN(0),
+      subAccount: 0,
+      fundFromBalance: false,
+      quoteAmount: new anchor.BN(1000),
+      side: {ask:{}},
+    })
+    .accounts({
+      user: keypair.publicKey.toBase58(),
+      userTokenAccount: userBaseTokenAccount.toBase58(),
+      market: market.publicKey.toBase58(),
+      tokenToTrade: baseToken.publicKey.toBase58(),
+      tokenVault: baseTokenVault.toBase58(),
+      bids: bidAccount.publicKey.toBase58(),
+      asks: askAccount.publicKey.toBase58(),   
+      marketEvents: marketEvent.publicKey.toBase58(),
+      referrerBalanceAccount: null,
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
+    })
+    .instruction();
 
-    await program.methods
-    .consumeEvents()
+    // the quote limit can only afford one of the two base lots
+    const ix2 = await program.methods
+    .placeOrder({
+      baseLots: 5,
+      orderType: {market:{quoteLimit: new anchor.BN(1500)}},
+      expiry: null,
+      selfTradeBehavior: {allow:{}},
+      clientOrderId: new anchor.BN(0),
+      subAccount: 0,
+      fundFromBalance: false,
+      quoteAmount: new anchor.BN(0),
+      side: {bid:{}},
+    })
     .accounts({
+      user: keypair.publicKey.toBase58(),
+      userTokenAccount: userQuoteTokenAccount.toBase58(),
       market: market.publicKey.toBase58(),
-      consumeEventsAuthority: keypair.publicKey.toBase58(),
+      tokenToTrade: quoteToken.publicKey.toBase58(),
+      tokenVault: quoteTokenVault.toBase58(),
+      bids: bidAccount.publicKey.toBase58(),
+      asks: askAccount.publicKey.toBase58(),   
       marketEvents: marketEvent.publicKey.toBase58(),
+      referrerBalanceAccount: null,
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
     })
-    .remainingAccounts(remainingAccounts)
-    .rpc({commitment: "confirmed"});
+    .instruction();
 
-    const balanceAfter = await program
-    .account
-    .userBalance
-    .fetch(userBalanceAccount);
+    const tx = new anchor.web3.Transaction();
 
-    const eventsAfter = await program.account.marketEvents.fetch(marketEvent.publicKey);
+    tx.add(
+      ix1,
+      ix2
+    );
 
-    expect(balanceAfter.baseAmount.toNumber()).to.be.equal(expectedBaseBalanceAmount);
-    expect(balanceAfter.quoteAmount.toNumber()).to.be.equal(expectedQuoteBalanceAmount);
+    await anchor.web3.sendAndConfirmTransaction(connection, tx, [keypair], {commitment: "confirmed", skipPreflight: true});
 
-    expect(eventsAfter.eventsToProcess.toNumber()).to.be.equal(0);
-    expect(eventsAfter.events[0].id.toNumber()).to.be.equal(0);
-    expect(eventsAfter.events[0].orderId.toNumber()).to.be.equal(0);
-    expect(eventsAfter.events[0].quoteAmount.toNumber()).to.be.equal(0);
-    expect(eventsAfter.events[0].baseAmount.toNumber()).to.be.equal(0);
+    const userQuoteTokenAccountAfter = await getAccount(connection, userQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+    const userBalanceAccAfter = await program.account.userBalance.fetch(userBalanceAccount);
+    const bidsAcc = await program.account.bookSide.fetch(bidAccount.publicKey);
+    const asksAcc = await program.account.bookSide.fetch(askAccount.publicKey);
+    const marketAcc = await program.account.market.fetch(market.publicKey);
 
-  })
+    // the whole quote limit is escrowed, and the unspent part is refunded to the balance account
+    expect(userQuoteTokenAccountAfter.amount).to.equal(userQuoteTokenAccountBefore.amount - BigInt(1500));
+    expect(userBalanceAccAfter.quoteAmount.toNumber()).to.be.equal(userBalanceAccBefore.quoteAmount.toNumber() + 500);
+    expect(userBalanceAccAfter.baseAmount.toNumber()).to.be.equal(userBalanceAccBefore.baseAmount.toNumber() + marketAcc.baseLotSize.toNumber());
 
-  it("Should be able to settle user balance !", async() => {
-  
-    const beforeBaseAccount = await getAccount(connection, userBaseTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
-    const beforeQuoteAccount = await getAccount(connection, userQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+    // the market order never sits on the orderbook
+    expect(bidsAcc.orderCount.toNumber()).to.equal(0);
+    expect(asksAcc.orderCount.toNumber()).to.equal(1);
+    expect(asksAcc.orders[0].baseAmount.toNumber()).to.equal(marketAcc.baseLotSize.toNumber());
+  })
 
-    const beforeUserBalanceAccount = await program.account.userBalance.fetch(userBalanceAccount);
+  it("Should refund the price improvement to a crossing bid", async() => {
 
-    const expectedBaseAmount = BigInt(beforeUserBalanceAccount.baseAmount.toNumber()) + beforeBaseAccount.amount;
-    const expectedQuoteAmount = BigInt(beforeUserBalanceAccount.quoteAmount.toNumber()) + beforeQuoteAccount.amount;
+    const userQuoteTokenAccountBefore = await getAccount(connection, userQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+    const userBalanceAccBefore = await program.account.userBalance.fetch(userBalanceAccount);
 
-    await program.methods
-    .settleUserBalance()
-    .accounts({
-      market: market.publicKey.toBase58(),
-      userBalanceAccount: userBalanceAccount.toBase58(),
+    const ix1 = await program.methods
+    .placeOrder({
+      baseLots: 1,
+      orderType: {limit:{}},
+      expiry: null,
+      selfTradeBehavior: {allow:{}},
+      clientOrderId: new anchor.BN(0),
+      subAccount: 0,
+      fundFromBalance: false,
+      quoteAmount: new anchor.BN(800),
+      side: {ask:{}},
+    })
+    .accounts({
+      user: keypair.publicKey.toBase58(),
+      userTokenAccount: userBaseTokenAccount.toBase58(),
+      market: market.publicKey.toBase58(),
+      tokenToTrade: baseToken.publicKey.toBase58(),
+      tokenVault: baseTokenVault.toBase58(),
+      bids: bidAccount.publicKey.toBase58(),
+      asks: askAccount.publicKey.toBase58(),   
+      marketEvents: marketEvent.publicKey.toBase58(),
+      referrerBalanceAccount: null,
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
+    })
+    .instruction();
+
+    const ix2 = await program.methods
+    .placeOrder({
+      baseLots: 1,
+      orderType: {limit:{}},
+      expiry: null,
+      selfTradeBehavior: {allow:{}},
+      clientOrderId: new anchor.BN(0),
+      subAccount: 0,
+      fundFromBalance: false,
+      quoteAmount: new anchor.BN(1000),
+      side: {bid:{}},
+    })
+    .accounts({
+      user: keypair.publicKey.toBase58(),
+      userTokenAccount: userQuoteTokenAccount.toBase58(),
+      market: market.publicKey.toBase58(),
+      tokenToTrade: quoteToken.publicKey.toBase58(),
+      tokenVault: quoteTokenVault.toBase58(),
+      bids: bidAccount.publicKey.toBase58(),
+      asks: askAccount.publicKey.toBase58(),   
+      marketEvents: marketEvent.publicKey.toBase58(),
+      referrerBalanceAccount: null,
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
+    })
+    .instruction();
+
+    const tx = new anchor.web3.Transaction();
+
+    tx.add(
+      ix1,
+      ix2
+    );
+
+    await anchor.web3.sendAndConfirmTransaction(connection, tx, [keypair], {commitment: "confirmed", skipPreflight: true});
+
+    const userQuoteTokenAccountAfter = await getAccount(connection, userQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+    const userBalanceAccAfter = await program.account.userBalance.fetch(userBalanceAccount);
+    const marketEventAcc = await program.account.marketEvents.fetch(marketEvent.publicKey);
+
+    // the bid escrows its limit price, but only pays the price of the resting ask
+    expect(userQuoteTokenAccountAfter.amount).to.equal(userQuoteTokenAccountBefore.amount - BigInt(1000));
+    expect(userBalanceAccAfter.quoteAmount.toNumber()).to.be.equal(userBalanceAccBefore.quoteAmount.toNumber() + 200);
+
+    const fillEvent = getEvent(marketEventAcc, marketEventAcc.count.toNumber() - 1);
+    expect(fillEvent.quoteAmount.toNumber()).to.be.equal(800);
+  })
+
+  it("Should prune the expired orders", async() => {
+
+    const currentSlot = await connection.getSlot("confirmed");
+
+    await program.methods
+    .placeOrder({
+      baseLots: 1,
+      orderType: {limit:{}},
+      expiry: {slot:{slot: new anchor.BN(currentSlot + 3)}},
+      selfTradeBehavior: {allow:{}},
+      clientOrderId: new anchor.BN(0),
+      subAccount: 0,
+      fundFromBalance: false,
+      quoteAmount: new anchor.BN(100),
+      side: {bid:{}},
+    })
+    .accounts({
+      user: keypair.publicKey.toBase58(),
+      userTokenAccount: userQuoteTokenAccount.toBase58(),
+      market: market.publicKey.toBase58(),
+      tokenToTrade: quoteToken.publicKey.toBase58(),
+      tokenVault: quoteTokenVault.toBase58(),
+      bids: bidAccount.publicKey.toBase58(),
+      asks: askAccount.publicKey.toBase58(),   
+      marketEvents: marketEvent.publicKey.toBase58(),
+      referrerBalanceAccount: null,
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
+    })
+    .rpc({commitment: "confirmed"});
+
+    const bidsBefore = await program.account.bookSide.fetch(bidAccount.publicKey);
+    expect(bidsBefore.orderCount.toNumber()).to.equal(1);
+
+    // wait for the order to expire
+    while (await connection.getSlot("confirmed") < currentSlot + 3) {
+      await new Promise((resolve) => setTimeout(resolve, 400));
+    }
+
+    await program.methods
+    .pruneExpiredOrders({
+      side: {bid:{}},
+      limit: 10,
+    })
+    .accounts({
+      market: market.publicKey.toBase58(),
+      booksideAccount: bidAccount.publicKey.toBase58(),
+    })
+    .rpc({commitment: "confirmed"});
+
+    const bidsAfter = await program.account.bookSide.fetch(bidAccount.publicKey);
+    const marketEventAcc = await program.account.marketEvents.fetch(marketEvent.publicKey);
+
+    expect(bidsAfter.orderCount.toNumber()).to.equal(0);
+    expect(bidsAfter.orders[0].orderId.toNumber()).to.equal(0);
+
+    const outEvent = getEvent(marketEventAcc, marketEventAcc.count.toNumber() - 1);
+    expect(outEvent.eventType.toNumber()).to.be.equal(1);
+    expect(outEvent.side.toNumber()).to.be.equal(0);
+    expect(outEvent.quoteAmount.toNumber()).to.be.equal(100);
+  })
+
+  it("Should stop a market bid at the order that exhausts its quote limit", async() => {
+
+    const currentSlot = await connection.getSlot("confirmed");
+
+    const placeAsk = (baseLots: number, quoteAmount: number, expiry: any) => program.methods
+    .placeOrder({
+      baseLots,
+      orderType: {limit:{}},
+      expiry,
+      selfTradeBehavior: {allow:{}},
+      clientOrderId: new anchor.BN(0),
+      subAccount: 0,
+      fundFromBalance: false,
+      quoteAmount: new anchor.BN(quoteAmount),
+      side: {ask:{}},
+    })
+    .accounts({
+      user: keypair.publicKey.toBase58(),
+      userTokenAccount: userBaseTokenAccount.toBase58(),
+      market: market.publicKey.toBase58(),
+      tokenToTrade: baseToken.publicKey.toBase58(),
+      tokenVault: baseTokenVault.toBase58(),
+      bids: bidAccount.publicKey.toBase58(),
+      asks: askAccount.publicKey.toBase58(),   
+      marketEvents: marketEvent.publicKey.toBase58(),
+      referrerBalanceAccount: null,
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
+    })
+    .instruction();
+
+    // an ask that only gets partially filled, followed by an ask that expires
+    const tx1 = new anchor.web3.Transaction();
+    tx1.add(
+      await placeAsk(2, 1000, null),
+      await placeAsk(1, 1100, {slot:{slot: new anchor.BN(currentSlot + 3)}}),
+    );
+    await anchor.web3.sendAndConfirmTransaction(connection, tx1, [keypair], {commitment: "confirmed"});
+
+    const asksBefore = await program.account.bookSide.fetch(askAccount.publicKey);
+    const userBalanceAccBefore = await program.account.userBalance.fetch(userBalanceAccount);
+    const marketAcc = await program.account.market.fetch(market.publicKey);
+    const partiallyFilledOrder = asksBefore.orders[1];
+    const expiredOrder = asksBefore.orders[2];
+
+    expect(asksBefore.orderCount.toNumber()).to.equal(3);
+    expect(expiredOrder.priceInTicks.toNumber()).to.equal(1100 / TICK_VALUE);
+
+    // wait for the order to expire
+    while (await connection.getSlot("confirmed") < currentSlot + 3) {
+      await new Promise((resolve) => setTimeout(resolve, 400));
+    }
+
+    // the quote limit affords the first ask, and only one of the two base lots of the second
+    await program.methods
+    .placeOrder({
+      baseLots: 5,
+      orderType: {market:{quoteLimit: new anchor.BN(2000)}},
+      expiry: null,
+      selfTradeBehavior: {allow:{}},
+      clientOrderId: new anchor.BN(0),
+      subAccount: 0,
+      fundFromBalance: false,
+      quoteAmount: new anchor.BN(0),
+      side: {bid:{}},
+    })
+    .accounts({
+      user: keypair.publicKey.toBase58(),
+      userTokenAccount: userQuoteTokenAccount.toBase58(),
+      market: market.publicKey.toBase58(),
+      tokenToTrade: quoteToken.publicKey.toBase58(),
+      tokenVault: quoteTokenVault.toBase58(),
+      bids: bidAccount.publicKey.toBase58(),
+      asks: askAccount.publicKey.toBase58(),   
+      marketEvents: marketEvent.publicKey.toBase58(),
+      referrerBalanceAccount: null,
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
+    })
+    .rpc({commitment: "confirmed"});
+
+    const asksAfter = await program.account.bookSide.fetch(askAccount.publicKey);
+    const userBalanceAccAfter = await program.account.userBalance.fetch(userBalanceAccount);
+    const marketEventAcc = await program.account.marketEvents.fetch(marketEvent.publicKey);
+
+    expect(userBalanceAccAfter.baseAmount.toNumber()).to.equal(userBalanceAccBefore.baseAmount.toNumber() + 2 * marketAcc.baseLotSize.toNumber());
+    expect(userBalanceAccAfter.quoteAmount.toNumber()).to.equal(userBalanceAccBefore.quoteAmount.toNumber());
+
+    // the partially filled ask keeps its remaining, and the expired ask is left for pruning
+    expect(asksAfter.orderCount.toNumber()).to.equal(2);
+    expect(asksAfter.orders[0].orderId.toNumber()).to.equal(partiallyFilledOrder.orderId.toNumber());
+    expect(asksAfter.orders[0].baseAmount.toNumber()).to.equal(marketAcc.baseLotSize.toNumber());
+    expect(asksAfter.orders[1].orderId.toNumber()).to.equal(expiredOrder.orderId.toNumber());
+
+    const fillEvent = getEvent(marketEventAcc, marketEventAcc.count.toNumber() - 1);
+    expect(fillEvent.eventType.toNumber()).to.be.equal(0);
+    expect(fillEvent.orderId.toNumber()).to.be.equal(partiallyFilledOrder.orderId.toNumber());
+    expect(fillEvent.baseAmount.toNumber()).to.be.equal(marketAcc.baseLotSize.toNumber());
+    expect(fillEvent.quoteAmount.toNumber()).to.be.equal(1000);
+
+    await program.methods
+    .pruneExpiredOrders({
+      side: {ask:{}},
+      limit: 10,
+    })
+    .accounts({
+      market: market.publicKey.toBase58(),
+      booksideAccount: askAccount.publicKey.toBase58(),
+    })
+    .rpc({commitment: "confirmed"});
+  })
+
+  it("Should stop consuming at the first event whose balance account is missing", async() => {
+    const eventsBefore = await program.account.marketEvents.fetch(marketEvent.publicKey);
+    const firstEvent = getEvent(eventsBefore, 0);
+
+    const signature = await program.methods
+    .consumeEvents()
+    .accounts({
+      market: market.publicKey.toBase58(),
+      consumeEventsAuthority: keypair.publicKey.toBase58(),
+      marketEvents: marketEvent.publicKey.toBase58(),
+    })
+    .rpc({commitment: "confirmed"});
+
+    const eventsAfter = await program.account.marketEvents.fetch(marketEvent.publicKey);
+
+    // nothing is consumed, the queue is left as it is
+    expect(eventsAfter.head.toNumber()).to.be.equal(eventsBefore.head.toNumber());
+    expect(eventsAfter.count.toNumber()).to.be.equal(eventsBefore.count.toNumber());
+    expect(getEvent(eventsAfter, 0).id.toNumber()).to.be.equal(firstEvent.id.toNumber());
+
+    // the missing balance accounts are returned, starting with the one of the first event
+    const tx = await connection.getTransaction(signature, {commitment: "confirmed", maxSupportedTransactionVersion: 0});
+    const returnData = Buffer.from(tx.meta.returnData.data[0], "base64");
+    const missingCount = returnData.readUInt32LE(0);
+    const firstMissing = new anchor.web3.PublicKey(returnData.subarray(4, 36));
+
+    expect(missingCount).to.be.gt(0);
+    expect(firstMissing.toBase58()).to.be.equal(
+      getBalanceAccount(firstEvent.maker, firstEvent.makerSubAccount).toBase58()
+    );
+  })
+
+  it("Should be able to consume events", async() => {
+
+    const balanceBefore = await program
+    .account
+    .userBalance
+    .fetch(userBalanceAccount);
+
+    const eventsBefore = await program.account.marketEvents.fetch(marketEvent.publicKey);
+    let expectedBaseBalanceAmount = balanceBefore.baseAmount.toNumber();
+    let expectedQuoteBalanceAmount = balanceBefore.quoteAmount.toNumber();
+
+    const remainingAccounts : {
+      pubkey:anchor.web3.PublicKey,
+      isSigner: boolean,
+      isWritable: boolean
+    }[] = [];
+
+    for(let i = 0; i < eventsBefore.count.toNumber(); i++) {
+      let event = getEvent(eventsBefore, i);
+
+    
+      if(event.side.toNumber() === 0){
+        // if the side is bid
+        if(event.eventType.toNumber() === 0){
+          // if it's a fill event
+          expectedBaseBalanceAmount += event.baseAmount.toNumber();
+        }
+        else{
+          // if its an out event
+          expectedQuoteBalanceAmount += event.quoteAmount.toNumber();
+        }
+
+      }
+      else{
+        // if the side is ask
+        if(event.eventType.toNumber() === 0){
+          // if it's a fill event
+          expectedQuoteBalanceAmount += event.quoteAmount.toNumber();
+        }
+        else{
+          expectedBaseBalanceAmount += event.baseAmount.toNumber();
+        }
+      }
+
+      const balanceAccount = getBalanceAccount(event.maker, event.makerSubAccount);
+      remainingAccounts.push({
+        pubkey: balanceAccount,
+        isSigner: false,
+        isWritable: true
+      });
+    }
+
+    await program.methods
+    .consumeEvents()
+    .accounts({
+      market: market.publicKey.toBase58(),
+      consumeEventsAuthority: keypair.publicKey.toBase58(),
+      marketEvents: marketEvent.publicKey.toBase58(),
+    })
+    .remainingAccounts(remainingAccounts)
+    .rpc({commitment: "confirmed"});
+
+    const balanceAfter = await program
+    .account
+    .userBalance
+    .fetch(userBalanceAccount);
+
+    const eventsAfter = await program.account.marketEvents.fetch(marketEvent.publicKey);
+
+    expect(balanceAfter.baseAmount.toNumber()).to.be.equal(expectedBaseBalanceAmount);
+    expect(balanceAfter.quoteAmount.toNumber()).to.be.equal(expectedQuoteBalanceAmount);
+
+    // the head moves past the consumed events, and the consumed events are cleared
+    expect(eventsAfter.count.toNumber()).to.be.equal(0);
+    expect(eventsAfter.head.toNumber()).to.be.equal(eventsBefore.head.toNumber() + eventsBefore.count.toNumber());
+    expect(eventsAfter.events[0].id.toNumber()).to.be.equal(0);
+    expect(eventsAfter.events[0].orderId.toNumber()).to.be.equal(0);
+    expect(eventsAfter.events[0].quoteAmount.toNumber()).to.be.equal(0);
+    expect(eventsAfter.events[0].baseAmount.toNumber()).to.be.equal(0);
+
+  })
+
+  it("Should be able to cancel an order by client order id", async() => {
+
+    await program.methods
+    .placeOrder({
+      baseLots: 1,
+      orderType: {postOnly:{}},
+      expiry: null,
+      selfTradeBehavior: {allow:{}},
+      clientOrderId: new anchor.BN(42),
+      subAccount: 0,
+      fundFromBalance: false,
+      quoteAmount: new anchor.BN(100),
+      side: {bid:{}},
+    })
+    .accounts({
+      user: keypair.publicKey.toBase58(),
+      userTokenAccount: userQuoteTokenAccount.toBase58(),
+      market: market.publicKey.toBase58(),
+      tokenToTrade: quoteToken.publicKey.toBase58(),
+      tokenVault: quoteTokenVault.toBase58(),
+      bids: bidAccount.publicKey.toBase58(),
+      asks: askAccount.publicKey.toBase58(),   
+      marketEvents: marketEvent.publicKey.toBase58(),
+      referrerBalanceAccount: null,
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
+    })
+    .rpc({commitment: "confirmed"});
+
+    const bidsBefore = await program.account.bookSide.fetch(bidAccount.publicKey);
+    expect(bidsBefore.orders[0].clientOrderId.toNumber()).to.equal(42);
+
+    await program.methods
+    .cancelOrderByClientId({
+      clientOrderId: new anchor.BN(42),
+      side: {bid:{}},
+      subAccount: 0,
+    })
+    .accounts({
+      user: keypair.publicKey.toBase58(),
+      booksideAccount: bidAccount.publicKey.toBase58(),
+      market: market.publicKey.toBase58(),
+    })
+    .rpc({commitment: "confirmed"});
+
+    const bidsAfter = await program.account.bookSide.fetch(bidAccount.publicKey);
+    const marketEventAcc = await program.account.marketEvents.fetch(marketEvent.publicKey);
+
+    expect(bidsAfter.orderCount.toNumber()).to.equal(bidsBefore.orderCount.toNumber() - 1);
+    expect(bidsAfter.orders[0].clientOrderId.toNumber()).to.equal(0);
+
+    const outEvent = getEvent(marketEventAcc, marketEventAcc.count.toNumber() - 1);
+    expect(outEvent.eventType.toNumber()).to.be.equal(1);
+    expect(outEvent.orderId.toNumber()).to.be.equal(bidsBefore.orders[0].orderId.toNumber());
+  })
+
+  it("Should be able to amend an order", async() => {
+
+    await program.methods
+    .placeOrder({
+      baseLots: 2,
+      orderType: {postOnly:{}},
+      expiry: null,
+      selfTradeBehavior: {allow:{}},
+      clientOrderId: new anchor.BN(0),
+      subAccount: 0,
+      fundFromBalance: false,
+      quoteAmount: new anchor.BN(100),
+      side: {bid:{}},
+    })
+    .accounts({
+      user: keypair.publicKey.toBase58(),
+      userTokenAccount: userQuoteTokenAccount.toBase58(),
+      market: market.publicKey.toBase58(),
+      tokenToTrade: quoteToken.publicKey.toBase58(),
+      tokenVault: quoteTokenVault.toBase58(),
+      bids: bidAccount.publicKey.toBase58(),
+      asks: askAccount.publicKey.toBase58(),   
+      marketEvents: marketEvent.publicKey.toBase58(),
+      referrerBalanceAccount: null,
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
+    })
+    .rpc({commitment: "confirmed"});
+
+    const bidsBefore = await program.account.bookSide.fetch(bidAccount.publicKey);
+    const userBalanceAccBefore = await program.account.userBalance.fetch(userBalanceAccount);
+    const marketAcc = await program.account.market.fetch(market.publicKey);
+
+    const orderId = bidsBefore.orders[0].orderId;
+
+    await program.methods
+    .amendOrder({
+      orderId,
+      side: {bid:{}},
+      baseLots: 1,
+      quoteAmount: new anchor.BN(100),
+      subAccount: 0,
+    })
+    .accounts({
+      user: keypair.publicKey.toBase58(),
+      market: market.publicKey.toBase58(),
+      bids: bidAccount.publicKey.toBase58(),
+      asks: askAccount.publicKey.toBase58(),
+    })
+    .rpc({commitment: "confirmed"});
+
+    const bidsAfter = await program.account.bookSide.fetch(bidAccount.publicKey);
+    const userBalanceAccAfter = await program.account.userBalance.fetch(userBalanceAccount);
+
+    // the reduced escrow is credited to the balance account, without an out event
+    expect(bidsAfter.orders[0].orderId.toNumber()).to.equal(orderId.toNumber());
+    expect(bidsAfter.orders[0].baseAmount.toNumber()).to.equal(marketAcc.baseLotSize.toNumber());
+    expect(userBalanceAccAfter.quoteAmount.toNumber()).to.equal(userBalanceAccBefore.quoteAmount.toNumber() + 100);
+  })
+
+  it("Should be able to place a batch of orders", async() => {
+
+    const userBaseTokenAccountBefore = await getAccount(connection, userBaseTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+    const userQuoteTokenAccountBefore = await getAccount(connection, userQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+    const bidsBefore = await program.account.bookSide.fetch(bidAccount.publicKey);
+    const asksBefore = await program.account.bookSide.fetch(askAccount.publicKey);
+
+    const newOrder = (side: "bid" | "ask", price: number) => ({
+      side: side === "bid" ? {bid:{}} : {ask:{}},
+      baseLots: 1,
+      quoteAmount: new anchor.BN(price),
+      expiry: null,
+      clientOrderId: new anchor.BN(price),
+    });
+
+    await program.methods
+    .placeOrders({
+      orders: [newOrder("bid", 80), newOrder("ask", 2000), newOrder("bid", 90)],
+      subAccount: 0,
+    })
+    .accounts({
+      user: keypair.publicKey.toBase58(),
+      market: market.publicKey.toBase58(),
+      bids: bidAccount.publicKey.toBase58(),
+      asks: askAccount.publicKey.toBase58(),
+      userBaseTokenAccount: userBaseTokenAccount.toBase58(),
+      userQuoteTokenAccount: userQuoteTokenAccount.toBase58(),
+      baseTokenVault: baseTokenVault.toBase58(),
+      quoteTokenVault: quoteTokenVault.toBase58(),
+      baseToken: baseToken.publicKey.toBase58(),
+      quoteToken: quoteToken.publicKey.toBase58(),
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
+    })
+    .rpc({commitment: "confirmed"});
+
+    const userBaseTokenAccountAfter = await getAccount(connection, userBaseTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+    const userQuoteTokenAccountAfter = await getAccount(connection, userQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+    const bidsAfter = await program.account.bookSide.fetch(bidAccount.publicKey);
+    const asksAfter = await program.account.bookSide.fetch(askAccount.publicKey);
+    const marketAcc = await program.account.market.fetch(market.publicKey);
+
+    expect(userQuoteTokenAccountAfter.amount).to.equal(userQuoteTokenAccountBefore.amount - BigInt(80 + 90));
+    expect(userBaseTokenAccountAfter.amount).to.equal(userBaseTokenAccountBefore.amount - BigInt(marketAcc.baseLotSize.toNumber()));
+
+    expect(bidsAfter.orderCount.toNumber()).to.equal(bidsBefore.orderCount.toNumber() + 2);
+    expect(asksAfter.orderCount.toNumber()).to.equal(asksBefore.orderCount.toNumber() + 1);
+
+    // the bids are sorted by the best price first
+    const bidPrices = bidsAfter.orders
+    .slice(0, bidsAfter.orderCount.toNumber())
+    .map((order) => order.priceInTicks.toNumber());
+
+    expect(bidPrices).to.deep.equal([...bidPrices].sort((a, b) => b - a));
+  })
+
+  it("Should be able to replace all the quotes of the user", async() => {
+
+    const userBalanceAccBefore = await program.account.userBalance.fetch(userBalanceAccount);
+    const eventsBefore = await program.account.marketEvents.fetch(marketEvent.publicKey);
+    const bidsBefore = await program.account.bookSide.fetch(bidAccount.publicKey);
+    const asksBefore = await program.account.bookSide.fetch(askAccount.publicKey);
+    const marketAcc = await program.account.market.fetch(market.publicKey);
+
+    // every order on the book belongs to the user, so all of them are refunded
+    let refundedQuote = 0;
+    let refundedBase = 0;
+
+    for (let i = 0; i < bidsBefore.orderCount.toNumber(); i++) {
+      const order = bidsBefore.orders[i];
+      refundedQuote += order.priceInTicks.toNumber() * TICK_VALUE * (order.baseAmount.toNumber() / marketAcc.baseLotSize.toNumber());
+    }
+
+    for (let i = 0; i < asksBefore.orderCount.toNumber(); i++) {
+      refundedBase += asksBefore.orders[i].baseAmount.toNumber();
+    }
+
+    await program.methods
+    .replaceQuotes({
+      orders: [
+        {side: {bid:{}}, baseLots: 1, quoteAmount: new anchor.BN(95), expiry: null, clientOrderId: new anchor.BN(1)},
+        {side: {ask:{}}, baseLots: 1, quoteAmount: new anchor.BN(3000), expiry: null, clientOrderId: new anchor.BN(2)},
+      ],
+      subAccount: 0,
+    })
+    .accounts({
+      user: keypair.publicKey.toBase58(),
+      market: market.publicKey.toBase58(),
+      bids: bidAccount.publicKey.toBase58(),
+      asks: askAccount.publicKey.toBase58(),
+      userBaseTokenAccount: userBaseTokenAccount.toBase58(),
+      userQuoteTokenAccount: userQuoteTokenAccount.toBase58(),
+      baseTokenVault: baseTokenVault.toBase58(),
+      quoteTokenVault: quoteTokenVault.toBase58(),
+      baseToken: baseToken.publicKey.toBase58(),
+      quoteToken: quoteToken.publicKey.toBase58(),
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
+    })
+    .rpc({commitment: "confirmed"});
+
+    const userBalanceAccAfter = await program.account.userBalance.fetch(userBalanceAccount);
+    const eventsAfter = await program.account.marketEvents.fetch(marketEvent.publicKey);
+    const bidsAfter = await program.account.bookSide.fetch(bidAccount.publicKey);
+    const asksAfter = await program.account.bookSide.fetch(askAccount.publicKey);
+
+    expect(bidsAfter.orderCount.toNumber()).to.equal(1);
+    expect(asksAfter.orderCount.toNumber()).to.equal(1);
+    expect(bidsAfter.orders[0].priceInTicks.toNumber()).to.equal(95 / TICK_VALUE);
+    expect(asksAfter.orders[0].priceInTicks.toNumber()).to.equal(3000 / TICK_VALUE);
+
+    // no out events, the escrow is netted inside the balance account
+    expect(eventsAfter.count.toNumber()).to.equal(eventsBefore.count.toNumber());
+    expect(userBalanceAccAfter.quoteAmount.toNumber()).to.equal(userBalanceAccBefore.quoteAmount.toNumber() + refundedQuote - 95);
+    expect(userBalanceAccAfter.baseAmount.toNumber()).to.equal(userBalanceAccBefore.baseAmount.toNumber() + refundedBase - marketAcc.baseLotSize.toNumber());
+
+    // only the new quotes stay locked
+    expect(userBalanceAccAfter.quoteLocked.toNumber()).to.equal(userBalanceAccBefore.quoteLocked.toNumber() - refundedQuote + 95);
+    expect(userBalanceAccAfter.baseLocked.toNumber()).to.equal(userBalanceAccBefore.baseLocked.toNumber() - refundedBase + marketAcc.baseLotSize.toNumber());
+  })
+
+  it("Should be able to cancel all the orders of the user", async() => {
+
+    const tx = new anchor.web3.Transaction();
+
+    for (const price of [100, 90]) {
+      const ix = await program.methods
+      .placeOrder({
+        baseLots: 1,
+        orderType: {postOnly:{}},
+        expiry: null,
+        selfTradeBehavior: {allow:{}},
+        clientOrderId: new anchor.BN(price),
+        subAccount: 0,
+        fundFromBalance: false,
+        quoteAmount: new anchor.BN(price),
+        side: {bid:{}},
+      })
+      .accounts({
+        user: keypair.publicKey.toBase58(),
+        userTokenAccount: userQuoteTokenAccount.toBase58(),
+        market: market.publicKey.toBase58(),
+        tokenToTrade: quoteToken.publicKey.toBase58(),
+        tokenVault: quoteTokenVault.toBase58(),
+        bids: bidAccount.publicKey.toBase58(),
+        asks: askAccount.publicKey.toBase58(),   
+        marketEvents: marketEvent.publicKey.toBase58(),
+        referrerBalanceAccount: null,
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .instruction();
+
+      tx.add(ix);
+    }
+
+    await anchor.web3.sendAndConfirmTransaction(connection, tx, [keypair], {commitment: "confirmed", skipPreflight: true});
+
+    const eventsBefore = await program.account.marketEvents.fetch(marketEvent.publicKey);
+    const bidsBefore = await program.account.bookSide.fetch(bidAccount.publicKey);
+    const asksBefore = await program.account.bookSide.fetch(askAccount.publicKey);
+
+    await program.methods
+    .cancelAllOrders({
+      side: null,
+      limit: null,
+      subAccount: 0,
+    })
+    .accounts({
+      user: keypair.publicKey.toBase58(),
+      market: market.publicKey.toBase58(),
+      bids: bidAccount.publicKey.toBase58(),
+      asks: askAccount.publicKey.toBase58(),
+    })
+    .rpc({commitment: "confirmed"});
+
+    const bidsAfter = await program.account.bookSide.fetch(bidAccount.publicKey);
+    const asksAfter = await program.account.bookSide.fetch(askAccount.publicKey);
+    const eventsAfter = await program.account.marketEvents.fetch(marketEvent.publicKey);
+
+    // every order on the book belongs to the user, so one out event for each
+    const cancelledCount = bidsBefore.orderCount.toNumber() + asksBefore.orderCount.toNumber();
+
+    expect(bidsAfter.orderCount.toNumber()).to.equal(0);
+    expect(asksAfter.orderCount.toNumber()).to.equal(0);
+    expect(eventsAfter.count.toNumber()).to.equal(eventsBefore.count.toNumber() + cancelledCount);
+  })
+
+  it("Should be able to deposit and withdraw from the user balance account", async() => {
+
+    const beforeBalanceAcc = await program.account.userBalance.fetch(userBalanceAccount);
+    const beforeBaseAccount = await getAccount(connection, userBaseTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+    const beforeQuoteAccount = await getAccount(connection, userQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+
+    const baseAmount = 5000;
+    const quoteAmount = 1000;
+
+    await program.methods
+    .deposit({
+      baseAmount: new anchor.BN(baseAmount),
+      quoteAmount: new anchor.BN(quoteAmount),
+      subAccount: 0,
+    })
+    .accounts({
+      user: keypair.publicKey.toBase58(),
+      market: market.publicKey.toBase58(),
+      userBaseTokenAccount: userBaseTokenAccount.toBase58(),
+      userQuoteTokenAccount: userQuoteTokenAccount.toBase58(),
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
+    })
+    .rpc({commitment: "confirmed"});
+
+    const depositedBalanceAcc = await program.account.userBalance.fetch(userBalanceAccount);
+
+    expect(depositedBalanceAcc.baseAmount.toNumber()).to.equal(beforeBalanceAcc.baseAmount.toNumber() + baseAmount);
+    expect(depositedBalanceAcc.quoteAmount.toNumber()).to.equal(beforeBalanceAcc.quoteAmount.toNumber() + quoteAmount);
+
+    await program.methods
+    .withdraw({
+      baseAmount: new anchor.BN(baseAmount),
+      quoteAmount: new anchor.BN(quoteAmount),
+      subAccount: 0,
+    })
+    .accounts({
+      user: keypair.publicKey.toBase58(),
+      market: market.publicKey.toBase58(),
+      userBaseTokenAccount: userBaseTokenAccount.toBase58(),
+      userQuoteTokenAccount: userQuoteTokenAccount.toBase58(),
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
+    })
+    .rpc({commitment: "confirmed"});
+
+    const afterBalanceAcc = await program.account.userBalance.fetch(userBalanceAccount);
+    const afterBaseAccount = await getAccount(connection, userBaseTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+    const afterQuoteAccount = await getAccount(connection, userQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+
+    expect(afterBalanceAcc.baseAmount.toNumber()).to.equal(beforeBalanceAcc.baseAmount.toNumber());
+    expect(afterBalanceAcc.quoteAmount.toNumber()).to.equal(beforeBalanceAcc.quoteAmount.toNumber());
+    expect(afterBaseAccount.amount).to.equal(beforeBaseAccount.amount);
+    expect(afterQuoteAccount.amount).to.equal(beforeQuoteAccount.amount);
+  })
+
+  it("Should fund an order from the deposited balance", async() => {
+
+    const quoteAmount = 100;
+
+    await program.methods
+    .deposit({
+      baseAmount: new anchor.BN(0),
+      quoteAmount: new anchor.BN(quoteAmount),
+      subAccount: 0,
+    })
+    .accounts({
+      user: keypair.publicKey.toBase58(),
+      market: market.publicKey.toBase58(),
+      userBaseTokenAccount: userBaseTokenAccount.toBase58(),
+      userQuoteTokenAccount: userQuoteTokenAccount.toBase58(),
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
+    })
+    .rpc({commitment: "confirmed"});
+
+    const beforeBalanceAcc = await program.account.userBalance.fetch(userBalanceAccount);
+    const beforeQuoteAccount = await getAccount(connection, userQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+
+    await program.methods
+    .placeOrder({
+      baseLots: 1,
+      orderType: {postOnly:{}},
+      expiry: null,
+      selfTradeBehavior: {allow:{}},
+      clientOrderId: new anchor.BN(0),
+      subAccount: 0,
+      fundFromBalance: true,
+      quoteAmount: new anchor.BN(quoteAmount),
+      side: {bid:{}}
+    })
+    .accounts({
+      user: keypair.publicKey.toBase58(),
+      userTokenAccount: userQuoteTokenAccount.toBase58(),
+      market: market.publicKey.toBase58(),
+      tokenToTrade: quoteToken.publicKey.toBase58(),
+      tokenVault: quoteTokenVault.toBase58(),
+      bids: bidAccount.publicKey.toBase58(),
+      asks: askAccount.publicKey.toBase58(),   
+      marketEvents: marketEvent.publicKey.toBase58(),
+      referrerBalanceAccount: null,
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
+    })
+    .rpc({commitment: "confirmed"});
+
+    const afterBalanceAcc = await program.account.userBalance.fetch(userBalanceAccount);
+    const afterQuoteAccount = await getAccount(connection, userQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+
+    // the escrow moved from the free balance to the locked balance, without any transfer
+    expect(afterQuoteAccount.amount).to.equal(beforeQuoteAccount.amount);
+    expect(afterBalanceAcc.quoteAmount.toNumber()).to.equal(beforeBalanceAcc.quoteAmount.toNumber() - quoteAmount);
+    expect(afterBalanceAcc.quoteLocked.toNumber()).to.equal(beforeBalanceAcc.quoteLocked.toNumber() + quoteAmount);
+  })
+
+  it("Should settle an exact amount to a token account of someone else", async() => {
+
+    const receiver = anchor.web3.Keypair.generate();
+    const quoteAmount = 10;
+
+    const receiverQuoteTokenAccount = await getOrCreateAssociatedTokenAccount(
+      connection,
+      keypair,
+      quoteToken.publicKey,
+      receiver.publicKey,
+      undefined,
+      undefined,
+      {commitment:"confirmed"},
+      TOKEN_2022_PROGRAM_ID
+    );
+
+    await program.methods
+    .deposit({
+      baseAmount: new anchor.BN(0),
+      quoteAmount: new anchor.BN(quoteAmount),
+      subAccount: 0,
+    })
+    .accounts({
+      user: keypair.publicKey.toBase58(),
+      market: market.publicKey.toBase58(),
+      userBaseTokenAccount: userBaseTokenAccount.toBase58(),
+      userQuoteTokenAccount: userQuoteTokenAccount.toBase58(),
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
+    })
+    .rpc({commitment: "confirmed"});
+
+    const beforeBalanceAcc = await program.account.userBalance.fetch(userBalanceAccount);
+
+    await program.methods
+    .settleUserBalance({
+      subAccount: 0,
+      baseAmount: new anchor.BN(0),
+      quoteAmount: new anchor.BN(quoteAmount),
+    })
+    .accounts({
+      market: market.publicKey.toBase58(),
+      baseDestinationAccount: userBaseTokenAccount.toBase58(),
+      quoteDestinationAccount: receiverQuoteTokenAccount.address.toBase58(),
+      tokenProgam: TOKEN_2022_PROGRAM_ID,
+    })
+    .rpc({commitment: "confirmed"});
+
+    const afterBalanceAcc = await program.account.userBalance.fetch(userBalanceAccount);
+    const afterReceiverAccount = await getAccount(connection, receiverQuoteTokenAccount.address, undefined, TOKEN_2022_PROGRAM_ID);
+
+    expect(afterReceiverAccount.amount).to.equal(BigInt(quoteAmount));
+    expect(afterBalanceAcc.quoteAmount.toNumber()).to.equal(beforeBalanceAcc.quoteAmount.toNumber() - quoteAmount);
+    expect(afterBalanceAcc.baseAmount.toNumber()).to.equal(beforeBalanceAcc.baseAmount.toNumber());
+  })
+
+  it("Should not let anyone else than the market operators settle to the owner", async() => {
+
+    const cranker = anchor.web3.Keypair.generate();
+
+    try {
+      await program.methods
+      .settleToOwner()
+      .accounts({
+        cranker: cranker.publicKey.toBase58(),
+        market: market.publicKey.toBase58(),
+        userBalanceAccount: userBalanceAccount.toBase58(),
+        ownerBaseTokenAccount: userBaseTokenAccount.toBase58(),
+        ownerQuoteTokenAccount: userQuoteTokenAccount.toBase58(),
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .signers([cranker])
+      .rpc({commitment: "confirmed"});
+
+      throw new Error("Settlement should not have been cranked by a random signer");
+    } catch (error) {
+      if(error instanceof anchor.AnchorError){
+        expect(error.error.errorCode.code).to.be.equal("UnauthorizedCranker");
+        expect(error.error.errorCode.number).to.be.equal(6018);
+      }
+      else{
+        throw error;
+      }
+    }
+  })
+
+  it("Should let the market operators settle the balance to the owner's associated token accounts", async() => {
+
+    const quoteAmount = 10;
+
+    await program.methods
+    .deposit({
+      baseAmount: new anchor.BN(0),
+      quoteAmount: new anchor.BN(quoteAmount),
+      subAccount: 0,
+    })
+    .accounts({
+      user: keypair.publicKey.toBase58(),
+      market: market.publicKey.toBase58(),
+      userBaseTokenAccount: userBaseTokenAccount.toBase58(),
+      userQuoteTokenAccount: userQuoteTokenAccount.toBase58(),
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
+    })
+    .rpc({commitment: "confirmed"});
+
+    const beforeBalanceAcc = await program.account.userBalance.fetch(userBalanceAccount);
+    const beforeBaseAccount = await getAccount(connection, userBaseTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+    const beforeQuoteAccount = await getAccount(connection, userQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+
+    await program.methods
+    .settleToOwner()
+    .accounts({
+      cranker: keypair.publicKey.toBase58(),
+      market: market.publicKey.toBase58(),
+      userBalanceAccount: userBalanceAccount.toBase58(),
+      ownerBaseTokenAccount: userBaseTokenAccount.toBase58(),
+      ownerQuoteTokenAccount: userQuoteTokenAccount.toBase58(),
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
+    })
+    .rpc({commitment: "confirmed"});
+
+    const afterBalanceAcc = await program.account.userBalance.fetch(userBalanceAccount);
+    const afterBaseAccount = await getAccount(connection, userBaseTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+    const afterQuoteAccount = await getAccount(connection, userQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+
+    expect(afterBaseAccount.amount).to.equal(beforeBaseAccount.amount + BigInt(beforeBalanceAcc.baseAmount.toNumber()));
+    expect(afterQuoteAccount.amount).to.equal(beforeQuoteAccount.amount + BigInt(beforeBalanceAcc.quoteAmount.toNumber()));
+    expect(afterBalanceAcc.baseAmount.toNumber()).to.equal(0);
+    expect(afterBalanceAcc.quoteAmount.toNumber()).to.equal(0);
+  })
+
+  it("Should settle to the owner without the token account of an empty side", async() => {
+
+    const quoteAmount = 10;
+
+    await program.methods
+    .deposit({
+      baseAmount: new anchor.BN(0),
+      quoteAmount: new anchor.BN(quoteAmount),
+      subAccount: 0,
+    })
+    .accounts({
+      user: keypair.publicKey.toBase58(),
+      market: market.publicKey.toBase58(),
       userBaseTokenAccount: userBaseTokenAccount.toBase58(),
       userQuoteTokenAccount: userQuoteTokenAccount.toBase58(),
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
+    })
+    .rpc({commitment: "confirmed"});
+
+    const beforeQuoteAccount = await getAccount(connection, userQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+
+    // The base side is empty, so its token account can be left out
+    await program.methods
+    .settleToOwner()
+    .accounts({
+      cranker: keypair.publicKey.toBase58(),
+      market: market.publicKey.toBase58(),
+      userBalanceAccount: userBalanceAccount.toBase58(),
+      ownerBaseTokenAccount: null,
+      ownerQuoteTokenAccount: userQuoteTokenAccount.toBase58(),
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
+    })
+    .rpc({commitment: "confirmed"});
+
+    const afterBalanceAcc = await program.account.userBalance.fetch(userBalanceAccount);
+    const afterQuoteAccount = await getAccount(connection, userQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+
+    expect(afterQuoteAccount.amount).to.equal(beforeQuoteAccount.amount + BigInt(quoteAmount));
+    expect(afterBalanceAcc.baseAmount.toNumber()).to.equal(0);
+    expect(afterBalanceAcc.quoteAmount.toNumber()).to.equal(0);
+  })
+
+  it("Should be able to settle user balance !", async() => {
+  
+    const beforeBaseAccount = await getAccount(connection, userBaseTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+    const beforeQuoteAccount = await getAccount(connection, userQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+
+    const beforeUserBalanceAccount = await program.account.userBalance.fetch(userBalanceAccount);
+
+    const expectedBaseAmount = BigInt(beforeUserBalanceAccount.baseAmount.toNumber()) + beforeBaseAccount.amount;
+    const expectedQuoteAmount = BigInt(beforeUserBalanceAccount.quoteAmount.toNumber()) + beforeQuoteAccount.amount;
+
+    await program.methods
+    .settleUserBalance({
+      subAccount: 0,
+      baseAmount: null,
+      quoteAmount: null,
+    })
+    .accounts({
+      market: market.publicKey.toBase58(),
+      baseDestinationAccount: userBaseTokenAccount.toBase58(),
+      quoteDestinationAccount: userQuoteTokenAccount.toBase58(),
       tokenProgam: TOKEN_2022_PROGRAM_ID,
     })
     .rpc({commitment: "confirmed"});
@@ -787,5 +2074,690 @@ describe("clobby", () => {
     expect(afterUserBalanceAccount.baseAmount.toNumber()).to.be.equal(0);
     expect(afterUserBalanceAccount.quoteAmount.toNumber()).to.be.equal(0);
   })
-});
 
+  it("Should be able to sweep the fees of the market", async() => {
+
+    const beforeVaultAccount = await getAccount(connection, quoteTokenVault, undefined, TOKEN_2022_PROGRAM_ID);
+    const beforeMarketAcc = await program.account.market.fetch(market.publicKey);
+
+    await program.methods
+    .sweepFees()
+    .accounts({
+      admin: keypair.publicKey.toBase58(),
+      market: market.publicKey.toBase58(),
+      feeDestination: userQuoteTokenAccount.toBase58(),
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
+    })
+    .rpc({commitment: "confirmed"});
+
+    const afterVaultAccount = await getAccount(connection, quoteTokenVault, undefined, TOKEN_2022_PROGRAM_ID);
+    const afterMarketAcc = await program.account.market.fetch(market.publicKey);
+
+    expect(afterMarketAcc.feesAccrued.toNumber()).to.be.equal(0);
+    expect(beforeVaultAccount.amount - afterVaultAccount.amount).to.be.equal(BigInt(beforeMarketAcc.feesAccrued.toNumber()));
+  })
+
+  it("Should not close a balance account with resting orders", async() => {
+    try {
+      await program.methods
+      .closeUserBalanceAccount({
+        subAccount: 0,
+      })
+      .accounts({
+        user: keypair.publicKey.toBase58(),
+        market: market.publicKey.toBase58(),
+      })
+      .rpc({commitment: "confirmed"});
+
+      throw new Error("Balance account with resting orders should not have been closed");
+    } catch (error) {
+      if(error instanceof anchor.AnchorError){
+        expect(error.error.errorCode.code).to.be.equal("OpenOrdersExist");
+        expect(error.error.errorCode.number).to.be.equal(6017);
+      }
+      else{
+        throw error;
+      }
+    }
+  })
+
+  it("Should be able to close an unused balance account", async() => {
+
+    const subAccount = 1;
+    const balanceAccount = getBalanceAccount(keypair.publicKey, subAccount);
+
+    await program.methods
+    .createUserBalanceAccount({
+      subAccount,
+    })
+    .accounts({
+      user: keypair.publicKey.toBase58(),
+      market: market.publicKey.toBase58(),
+    })
+    .rpc({commitment: "confirmed"});
+
+    expect(await program.account.userBalance.fetchNullable(balanceAccount)).to.not.be.null;
+
+    await program.methods
+    .closeUserBalanceAccount({
+      subAccount,
+    })
+    .accounts({
+      user: keypair.publicKey.toBase58(),
+      market: market.publicKey.toBase58(),
+    })
+    .rpc({commitment: "confirmed"});
+
+    expect(await program.account.userBalance.fetchNullable(balanceAccount)).to.be.null;
+  })
+
+  // each test creates its own market, so the orderbook and the fees start from a known state
+  describe("fresh markets", () => {
+
+    const FEE_PRICE = 10_000;
+    const FEE_BASE_LOTS = 2;
+    const FEE_QUOTE_AMOUNT = FEE_PRICE * FEE_BASE_LOTS;
+    const FEE_BPS_MAKER = 10;
+    const FEE_BPS_TAKER = 30;
+
+    type FeeMarket = {
+      market: anchor.web3.Keypair,
+      bids: anchor.web3.Keypair,
+      asks: anchor.web3.Keypair,
+      marketEvents: anchor.web3.Keypair,
+      baseTokenVault: anchor.web3.PublicKey,
+      quoteTokenVault: anchor.web3.PublicKey,
+    };
+
+    // creates a market with the given fees, and the balance accounts of the sub accounts 0 and 1
+    const createFeeMarket = async (makerFeeBps: number, takerFeeBps: number): Promise<FeeMarket> => {
+      const feeMarket = anchor.web3.Keypair.generate();
+      const bids = anchor.web3.Keypair.generate();
+      const asks = anchor.web3.Keypair.generate();
+      const marketEvents = anchor.web3.Keypair.generate();
+
+      const lamports = await connection.getMinimumBalanceForRentExemption(MAX_ACCOUNT_SPACE);
+
+      for (const account of [bids, asks, marketEvents]) {
+        const tx = new anchor.web3.Transaction().add(
+          anchor.web3.SystemProgram.createAccount({
+            fromPubkey: keypair.publicKey,
+            newAccountPubkey: account.publicKey,
+            space: MAX_ACCOUNT_SPACE,
+            lamports,
+            programId: PROGRAM_ID,
+          }),
+        );
+        await anchor.web3.sendAndConfirmTransaction(connection, tx, [keypair, account], {commitment: "confirmed"});
+      }
+
+      const createMarketIx = await program.methods
+      .createMarket({
+        name: "FEE_MARKET",
+        baseLotSize: new anchor.BN(1000),
+        quoteLotSize: new anchor.BN(QUOTE_LOT_SIZE),
+        tickSize: new anchor.BN(TICK_SIZE),
+        minBaseLots: new anchor.BN(MIN_BASE_LOTS),
+        maxBaseLots: new anchor.BN(MAX_BASE_LOTS),
+        minNotional: new anchor.BN(MIN_NOTIONAL),
+        makerFeeBps,
+        takerFeeBps,
+        referrerShareBps: REFERRER_SHARE_BPS,
+        consumeEventsAuthority: keypair.publicKey,
+      })
+      .accounts({
+        market: feeMarket.publicKey.toBase58(),
+        bids: bids.publicKey.toBase58(),
+        asks: asks.publicKey.toBase58(),
+        signer: keypair.publicKey.toBase58(),
+        baseToken: baseToken.publicKey.toBase58(),
+        quoteToken: quoteToken.publicKey.toBase58(),
+        marketEvents: marketEvents.publicKey.toBase58(),
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .instruction();
+
+      const initAuthorityAndEventIx = await program.methods
+      .initMarketAuthorityAndEvent()
+      .accounts({
+        market: feeMarket.publicKey.toBase58(),
+        marketEvent: marketEvents.publicKey.toBase58(),
+        user: keypair.publicKey.toBase58(),
+      })
+      .instruction();
+
+      const createBooksideAccountsIx = await program.methods
+      .createBooksideAccounts()
+      .accounts({
+        asks: asks.publicKey.toBase58(),
+        bids: bids.publicKey.toBase58(),
+        market: feeMarket.publicKey.toBase58(),
+      })
+      .instruction();
+
+      const tx = new anchor.web3.Transaction().add(createMarketIx, initAuthorityAndEventIx, createBooksideAccountsIx);
+      await anchor.web3.sendAndConfirmTransaction(connection, tx, [keypair, feeMarket], {commitment: "confirmed", skipPreflight: true});
+
+      for (const subAccount of [0, 1]) {
+        await program.methods
+        .createUserBalanceAccount({
+          subAccount,
+        })
+        .accounts({
+          user: keypair.publicKey.toBase58(),
+          market: feeMarket.publicKey.toBase58(),
+        })
+        .rpc({commitment: "confirmed"});
+      }
+
+      const [feeMarketAuthority] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("market"), feeMarket.publicKey.toBuffer()],
+        PROGRAM_ID,
+      );
+
+      return {
+        market: feeMarket,
+        bids,
+        asks,
+        marketEvents,
+        baseTokenVault: getAssociatedTokenAddressSync(baseToken.publicKey, feeMarketAuthority, true, TOKEN_2022_PROGRAM_ID),
+        quoteTokenVault: getAssociatedTokenAddressSync(quoteToken.publicKey, feeMarketAuthority, true, TOKEN_2022_PROGRAM_ID),
+      };
+    }
+
+    type OrderOptions = {
+      price?: number,
+      baseLots?: number,
+      orderType?: any,
+      selfTradeBehavior?: any,
+    };
+
+    const placeFeeMarketOrder = (
+      feeMarket: FeeMarket, 
+      side: "bid" | "ask", 
+      subAccount: number, 
+      referrerBalanceAccount: anchor.web3.PublicKey | null = null,
+      expiry: any = null,
+      {price = FEE_PRICE, baseLots = FEE_BASE_LOTS, orderType = {limit:{}}, selfTradeBehavior = {allow:{}}}: OrderOptions = {},
+    ) => program.methods
+    .placeOrder({
+      baseLots,
+      orderType,
+      expiry,
+      selfTradeBehavior,
+      clientOrderId: new anchor.BN(0),
+      subAccount,
+      fundFromBalance: false,
+      quoteAmount: new anchor.BN(price),
+      side: side === "bid" ? {bid:{}} : {ask:{}},
+    })
+    .accounts({
+      user: keypair.publicKey.toBase58(),
+      userTokenAccount: (side === "bid" ? userQuoteTokenAccount : userBaseTokenAccount).toBase58(),
+      market: feeMarket.market.publicKey.toBase58(),
+      tokenToTrade: (side === "bid" ? quoteToken : baseToken).publicKey.toBase58(),
+      tokenVault: (side === "bid" ? feeMarket.quoteTokenVault : feeMarket.baseTokenVault).toBase58(),
+      bids: feeMarket.bids.publicKey.toBase58(),
+      asks: feeMarket.asks.publicKey.toBase58(),
+      marketEvents: feeMarket.marketEvents.publicKey.toBase58(),
+      referrerBalanceAccount: referrerBalanceAccount ? referrerBalanceAccount.toBase58() : null,
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
+    })
+    .rpc({commitment: "confirmed"});
+
+    const consumeFeeMarketEvents = (feeMarket: FeeMarket) => program.methods
+    .consumeEvents()
+    .accounts({
+      market: feeMarket.market.publicKey.toBase58(),
+      consumeEventsAuthority: keypair.publicKey.toBase58(),
+      marketEvents: feeMarket.marketEvents.publicKey.toBase58(),
+    })
+    .remainingAccounts([{
+      pubkey: getBalanceAccount(keypair.publicKey, 0, feeMarket.market.publicKey),
+      isSigner: false,
+      isWritable: true,
+    }])
+    .rpc({commitment: "confirmed"});
+
+    let feeMarket: FeeMarket;
+
+    it("Should charge the maker and taker fees, and sweep them", async() => {
+
+      feeMarket = await createFeeMarket(FEE_BPS_MAKER, FEE_BPS_TAKER);
+
+      const makerBalanceAccount = getBalanceAccount(keypair.publicKey, 0, feeMarket.market.publicKey);
+      const takerBalanceAccount = getBalanceAccount(keypair.publicKey, 1, feeMarket.market.publicKey);
+
+      const makerFee = FEE_PRICE * FEE_BPS_MAKER / 10_000 * FEE_BASE_LOTS;
+      const takerFee = Math.ceil(FEE_QUOTE_AMOUNT * FEE_BPS_TAKER / 10_000);
+
+      // the resting bid escrows its maker fee on top of its quote amount
+      const userQuoteTokenAccountBefore = await getAccount(connection, userQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+      await placeFeeMarketOrder(feeMarket, "bid", 0);
+      const userQuoteTokenAccountAfter = await getAccount(connection, userQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+
+      expect(userQuoteTokenAccountBefore.amount - userQuoteTokenAccountAfter.amount).to.equal(BigInt(FEE_QUOTE_AMOUNT + makerFee));
+      expect((await program.account.userBalance.fetch(makerBalanceAccount)).quoteLocked.toNumber()).to.equal(FEE_QUOTE_AMOUNT + makerFee);
+
+      // the taker fee is deducted from the quote amount received by the ask
+      await placeFeeMarketOrder(feeMarket, "ask", 1);
+
+      const takerBalanceAcc = await program.account.userBalance.fetch(takerBalanceAccount);
+      expect(takerBalanceAcc.quoteAmount.toNumber()).to.equal(FEE_QUOTE_AMOUNT - takerFee);
+      expect((await program.account.market.fetch(feeMarket.market.publicKey)).feesAccrued.toNumber()).to.equal(takerFee);
+
+      // the maker fee is taken out of the escrow, once the fill is consumed
+      await consumeFeeMarketEvents(feeMarket);
+
+      const makerBalanceAcc = await program.account.userBalance.fetch(makerBalanceAccount);
+      expect(makerBalanceAcc.baseAmount.toNumber()).to.equal(FEE_BASE_LOTS * 1000);
+      expect(makerBalanceAcc.quoteAmount.toNumber()).to.equal(0);
+      expect(makerBalanceAcc.quoteLocked.toNumber()).to.equal(0);
+
+      const marketAcc = await program.account.market.fetch(feeMarket.market.publicKey);
+      expect(marketAcc.feesAccrued.toNumber()).to.equal(takerFee + makerFee);
+
+      const feeDestinationBefore = await getAccount(connection, userQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+
+      await program.methods
+      .sweepFees()
+      .accounts({
+        admin: keypair.publicKey.toBase58(),
+        market: feeMarket.market.publicKey.toBase58(),
+        feeDestination: userQuoteTokenAccount.toBase58(),
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .rpc({commitment: "confirmed"});
+
+      const feeDestinationAfter = await getAccount(connection, userQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+
+      expect(feeDestinationAfter.amount - feeDestinationBefore.amount).to.equal(BigInt(takerFee + makerFee));
+      expect((await program.account.market.fetch(feeMarket.market.publicKey)).feesAccrued.toNumber()).to.equal(0);
+    })
+
+    it("Should pay the maker rebates out of the taker fee", async() => {
+
+      const rebateMarket = await createFeeMarket(-FEE_BPS_MAKER, FEE_BPS_TAKER);
+
+      const makerBalanceAccount = getBalanceAccount(keypair.publicKey, 0, rebateMarket.market.publicKey);
+      const takerBalanceAccount = getBalanceAccount(keypair.publicKey, 1, rebateMarket.market.publicKey);
+
+      const makerRebate = FEE_PRICE * FEE_BPS_MAKER / 10_000 * FEE_BASE_LOTS;
+      const takerFee = Math.ceil(FEE_QUOTE_AMOUNT * FEE_BPS_TAKER / 10_000);
+
+      await placeFeeMarketOrder(rebateMarket, "ask", 0);
+
+      // the taker fee is paid on top of the quote amount by the bid
+      const userQuoteTokenAccountBefore = await getAccount(connection, userQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+      await placeFeeMarketOrder(rebateMarket, "bid", 1);
+      const userQuoteTokenAccountAfter = await getAccount(connection, userQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+
+      expect(userQuoteTokenAccountBefore.amount - userQuoteTokenAccountAfter.amount).to.equal(BigInt(FEE_QUOTE_AMOUNT + takerFee));
+      expect((await program.account.userBalance.fetch(takerBalanceAccount)).baseAmount.toNumber()).to.equal(FEE_BASE_LOTS * 1000);
+
+      // the rebate is set aside from the taker fee, when the orders match
+      expect((await program.account.market.fetch(rebateMarket.market.publicKey)).feesAccrued.toNumber()).to.equal(takerFee - makerRebate);
+
+      await consumeFeeMarketEvents(rebateMarket);
+
+      const makerBalanceAcc = await program.account.userBalance.fetch(makerBalanceAccount);
+      expect(makerBalanceAcc.quoteAmount.toNumber()).to.equal(FEE_QUOTE_AMOUNT + makerRebate);
+      expect(makerBalanceAcc.baseLocked.toNumber()).to.equal(0);
+      expect((await program.account.market.fetch(rebateMarket.market.publicKey)).feesAccrued.toNumber()).to.equal(takerFee - makerRebate);
+    })
+
+    it("Should share the taker fee with the referrer", async() => {
+
+      const referrer = anchor.web3.Keypair.generate();
+      const referrerBalanceAccount = getBalanceAccount(referrer.publicKey, 0, feeMarket.market.publicKey);
+
+      const airdropSignature = await connection.requestAirdrop(referrer.publicKey, anchor.web3.LAMPORTS_PER_SOL);
+      await connection.confirmTransaction(airdropSignature, "confirmed");
+
+      await program.methods
+      .createUserBalanceAccount({
+        subAccount: 0,
+      })
+      .accounts({
+        user: referrer.publicKey.toBase58(),
+        market: feeMarket.market.publicKey.toBase58(),
+      })
+      .signers([referrer])
+      .rpc({commitment: "confirmed"});
+
+      const takerFee = Math.ceil(FEE_QUOTE_AMOUNT * FEE_BPS_TAKER / 10_000);
+      const referrerFee = Math.floor(takerFee * REFERRER_SHARE_BPS / 10_000);
+
+      const marketAccBefore = await program.account.market.fetch(feeMarket.market.publicKey);
+
+      await placeFeeMarketOrder(feeMarket, "bid", 0);
+      await placeFeeMarketOrder(feeMarket, "ask", 1, referrerBalanceAccount);
+
+      const referrerBalanceAcc = await program.account.userBalance.fetch(referrerBalanceAccount);
+      const marketAccAfter = await program.account.market.fetch(feeMarket.market.publicKey);
+
+      expect(referrerFee).to.be.gt(0);
+      expect(referrerBalanceAcc.quoteAmount.toNumber()).to.equal(referrerFee);
+      expect(marketAccAfter.feesAccrued.toNumber() - marketAccBefore.feesAccrued.toNumber()).to.equal(takerFee - referrerFee);
+    })
+
+    it("Should unlock the escrow of filled, cancelled and expired orders, once the events are consumed", async() => {
+
+      const lockMarket = await createFeeMarket(FEE_BPS_MAKER, FEE_BPS_TAKER);
+      const makerBalanceAccount = getBalanceAccount(keypair.publicKey, 0, lockMarket.market.publicKey);
+
+      // the maker fee is escrowed and locked along with the quote amount of a bid
+      const makerFee = FEE_PRICE * FEE_BPS_MAKER / 10_000 * FEE_BASE_LOTS;
+      const bidEscrow = FEE_QUOTE_AMOUNT + makerFee;
+      const askEscrow = FEE_BASE_LOTS * 1000;
+
+      const expectLocked = async (quoteLocked: number, baseLocked: number) => {
+        const balanceAcc = await program.account.userBalance.fetch(makerBalanceAccount);
+        expect(balanceAcc.quoteLocked.toNumber()).to.equal(quoteLocked);
+        expect(balanceAcc.baseLocked.toNumber()).to.equal(baseLocked);
+      }
+
+      // fills, the escrow stays locked until the fill events are consumed
+      await placeFeeMarketOrder(lockMarket, "bid", 0);
+      await placeFeeMarketOrder(lockMarket, "ask", 1);
+      await placeFeeMarketOrder(lockMarket, "ask", 0);
+      await placeFeeMarketOrder(lockMarket, "bid", 1);
+      await expectLocked(bidEscrow, askEscrow);
+
+      await consumeFeeMarketEvents(lockMarket);
+      await expectLocked(0, 0);
+
+      // cancels
+      await placeFeeMarketOrder(lockMarket, "bid", 0);
+      await expectLocked(bidEscrow, 0);
+
+      const bidsAcc = await program.account.bookSide.fetch(lockMarket.bids.publicKey);
+
+      await program.methods
+      .cancelOrder({
+        orderId: bidsAcc.orders[0].orderId,
+        side: {bid:{}},
+        subAccount: 0,
+      })
+      .accounts({
+        user: keypair.publicKey.toBase58(),
+        booksideAccount: lockMarket.bids.publicKey.toBase58(),
+        market: lockMarket.market.publicKey.toBase58(),
+      })
+      .rpc({commitment: "confirmed"});
+
+      await consumeFeeMarketEvents(lockMarket);
+      await expectLocked(0, 0);
+
+      // pruning
+      const currentSlot = await connection.getSlot("confirmed");
+      await placeFeeMarketOrder(lockMarket, "ask", 0, null, {slot:{slot: new anchor.BN(currentSlot + 3)}});
+      await expectLocked(0, askEscrow);
+
+      // wait for the order to expire
+      while (await connection.getSlot("confirmed") < currentSlot + 3) {
+        await new Promise((resolve) => setTimeout(resolve, 400));
+      }
+
+      await program.methods
+      .pruneExpiredOrders({
+        side: {ask:{}},
+        limit: 10,
+      })
+      .accounts({
+        market: lockMarket.market.publicKey.toBase58(),
+        booksideAccount: lockMarket.asks.publicKey.toBase58(),
+      })
+      .rpc({commitment: "confirmed"});
+
+      await consumeFeeMarketEvents(lockMarket);
+      await expectLocked(0, 0);
+
+      // the filled bid received the base amount, the filled ask the quote amount after its maker fee,
+      // and the cancelled bid and the expired ask got back their whole escrow
+      const balanceAcc = await program.account.userBalance.fetch(makerBalanceAccount);
+      expect(balanceAcc.baseAmount.toNumber()).to.equal(askEscrow + askEscrow);
+      expect(balanceAcc.quoteAmount.toNumber()).to.equal(FEE_QUOTE_AMOUNT - makerFee + bidEscrow);
+    })
+
+    it("Should reach a crossing order behind more expired orders than the match limit", async() => {
+
+      const matchingMarket = await createFeeMarket(0, 0);
+      const takerBalanceAccount = getBalanceAccount(keypair.publicKey, 1, matchingMarket.market.publicKey);
+
+      const expiredAsksCount = 5;
+      const expiredPrice = FEE_PRICE - 1000;
+      const currentSlot = await connection.getSlot("confirmed");
+      const expiry = {slot:{slot: new anchor.BN(currentSlot + 20)}};
+
+      // the expired asks sit at the top of the book, in front of a live crossing ask
+      for (let i = 0; i < expiredAsksCount; i++) {
+        await placeFeeMarketOrder(matchingMarket, "ask", 0, null, expiry, {price: expiredPrice});
+      }
+      await placeFeeMarketOrder(matchingMarket, "ask", 0);
+
+      const liveAsk = (await program.account.bookSide.fetch(matchingMarket.asks.publicKey)).orders[expiredAsksCount];
+      expect(liveAsk.priceInTicks.toNumber()).to.equal(FEE_PRICE / TICK_VALUE);
+
+      // wait for the orders to expire
+      while (await connection.getSlot("confirmed") < currentSlot + 20) {
+        await new Promise((resolve) => setTimeout(resolve, 400));
+      }
+
+      // removing the expired asks does not use up the match limit, so the live ask is still reached
+      try {
+        await placeFeeMarketOrder(matchingMarket, "bid", 1, null, null, {orderType: {postOnly:{}}});
+
+        throw new Error("Post only order should not have crossed the live ask");
+      } catch (error) {
+        if(error instanceof anchor.AnchorError){
+          expect(error.error.errorCode.code).to.be.equal("PostOnlyWouldCross");
+          expect(error.error.errorCode.number).to.be.equal(6006);
+        }
+        else{
+          throw error;
+        }
+      }
+
+      await placeFeeMarketOrder(matchingMarket, "bid", 1);
+
+      const asksAcc = await program.account.bookSide.fetch(matchingMarket.asks.publicKey);
+      const bidsAcc = await program.account.bookSide.fetch(matchingMarket.bids.publicKey);
+      const marketEventAcc = await program.account.marketEvents.fetch(matchingMarket.marketEvents.publicKey);
+
+      expect(asksAcc.orderCount.toNumber()).to.equal(0);
+      expect(bidsAcc.orderCount.toNumber()).to.equal(0);
+      expect((await program.account.userBalance.fetch(takerBalanceAccount)).baseAmount.toNumber()).to.equal(FEE_BASE_LOTS * 1000);
+
+      // an out event for every expired ask, followed by the fill of the live ask
+      expect(marketEventAcc.count.toNumber()).to.equal(expiredAsksCount + 1);
+
+      for (let i = 0; i < expiredAsksCount; i++) {
+        expect(getEvent(marketEventAcc, i).eventType.toNumber()).to.be.equal(1);
+      }
+
+      const fillEvent = getEvent(marketEventAcc, expiredAsksCount);
+      expect(fillEvent.eventType.toNumber()).to.be.equal(0);
+      expect(fillEvent.orderId.toNumber()).to.be.equal(liveAsk.orderId.toNumber());
+      expect(fillEvent.quoteAmount.toNumber()).to.be.equal(FEE_QUOTE_AMOUNT);
+    })
+
+    it("Should not count a self trade decrement as filled for a FOK order", async() => {
+
+      const selfTradeMarket = await createFeeMarket(0, 0);
+
+      await placeFeeMarketOrder(selfTradeMarket, "ask", 0);
+
+      try {
+        await placeFeeMarketOrder(selfTradeMarket, "bid", 1, null, null, {
+          orderType: {fillOrKill:{}},
+          selfTradeBehavior: {decrementAndCancel:{}},
+        });
+
+        throw new Error("FOK order that only decremented its own order should not have succeeded");
+      } catch (error) {
+        if(error instanceof anchor.AnchorError){
+          expect(error.error.errorCode.code).to.be.equal("OrderFilledPartially");
+          expect(error.error.errorCode.number).to.be.equal(6001);
+        }
+        else{
+          throw error;
+        }
+      }
+
+      const asksAcc = await program.account.bookSide.fetch(selfTradeMarket.asks.publicKey);
+      expect(asksAcc.orderCount.toNumber()).to.equal(1);
+      expect(asksAcc.orders[0].baseAmount.toNumber()).to.equal(FEE_BASE_LOTS * 1000);
+    })
+
+    // places a resting bid on the sub account 0, and a crossing self trading ask on the sub account 1.
+    // returns the base amount transferred by the ask
+    const placeSelfTrade = async (selfTradeMarket: FeeMarket, selfTradeBehavior: any, makerBaseLots: number, takerBaseLots: number) => {
+      await placeFeeMarketOrder(selfTradeMarket, "bid", 0, null, null, {baseLots: makerBaseLots});
+
+      const userBaseTokenAccountBefore = await getAccount(connection, userBaseTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+      await placeFeeMarketOrder(selfTradeMarket, "ask", 1, null, null, {baseLots: takerBaseLots, selfTradeBehavior});
+      const userBaseTokenAccountAfter = await getAccount(connection, userBaseTokenAccount, undefined, TOKEN_2022_PROGRAM_ID);
+
+      return userBaseTokenAccountBefore.amount - userBaseTokenAccountAfter.amount;
+    }
+
+    it("Should cancel the self trading maker order", async() => {
+
+      const selfTradeMarket = await createFeeMarket(0, 0);
+      const makerBalanceAccount = getBalanceAccount(keypair.publicKey, 0, selfTradeMarket.market.publicKey);
+
+      const transferredBaseAmount = await placeSelfTrade(selfTradeMarket, {cancelMaker:{}}, FEE_BASE_LOTS, FEE_BASE_LOTS);
+
+      // the maker bid is removed, and the whole ask rests on the orderbook instead
+      const bidsAcc = await program.account.bookSide.fetch(selfTradeMarket.bids.publicKey);
+      const asksAcc = await program.account.bookSide.fetch(selfTradeMarket.asks.publicKey);
+      expect(bidsAcc.orderCount.toNumber()).to.equal(0);
+      expect(asksAcc.orderCount.toNumber()).to.equal(1);
+      expect(asksAcc.orders[0].baseAmount.toNumber()).to.equal(FEE_BASE_LOTS * 1000);
+      expect(transferredBaseAmount).to.equal(BigInt(FEE_BASE_LOTS * 1000));
+
+      // the maker gets back the whole escrow of the bid through an out event
+      const marketEventAcc = await program.account.marketEvents.fetch(selfTradeMarket.marketEvents.publicKey);
+      expect(marketEventAcc.count.toNumber()).to.equal(1);
+
+      const outEvent = getEvent(marketEventAcc, 0);
+      expect(outEvent.eventType.toNumber()).to.be.equal(1);
+      expect(outEvent.side.toNumber()).to.be.equal(0);
+      expect(outEvent.baseAmount.toNumber()).to.be.equal(FEE_BASE_LOTS * 1000);
+      expect(outEvent.quoteAmount.toNumber()).to.be.equal(FEE_QUOTE_AMOUNT);
+
+      await consumeFeeMarketEvents(selfTradeMarket);
+
+      const makerBalanceAcc = await program.account.userBalance.fetch(makerBalanceAccount);
+      expect(makerBalanceAcc.quoteAmount.toNumber()).to.equal(FEE_QUOTE_AMOUNT);
+      expect(makerBalanceAcc.quoteLocked.toNumber()).to.equal(0);
+    })
+
+    it("Should cancel the remaining of the self trading taker order", async() => {
+
+      const selfTradeMarket = await createFeeMarket(0, 0);
+      const makerBalanceAccount = getBalanceAccount(keypair.publicKey, 0, selfTradeMarket.market.publicKey);
+
+      const transferredBaseAmount = await placeSelfTrade(selfTradeMarket, {cancelTaker:{}}, FEE_BASE_LOTS, FEE_BASE_LOTS);
+
+      // the maker bid is left untouched, and nothing of the ask is escrowed
+      const bidsAcc = await program.account.bookSide.fetch(selfTradeMarket.bids.publicKey);
+      const asksAcc = await program.account.bookSide.fetch(selfTradeMarket.asks.publicKey);
+      expect(bidsAcc.orderCount.toNumber()).to.equal(1);
+      expect(bidsAcc.orders[0].baseAmount.toNumber()).to.equal(FEE_BASE_LOTS * 1000);
+      expect(asksAcc.orderCount.toNumber()).to.equal(0);
+      expect(transferredBaseAmount).to.equal(BigInt(0));
+
+      // no out event, the maker keeps its escrow locked
+      const marketEventAcc = await program.account.marketEvents.fetch(selfTradeMarket.marketEvents.publicKey);
+      expect(marketEventAcc.count.toNumber()).to.equal(0);
+
+      await consumeFeeMarketEvents(selfTradeMarket);
+
+      const makerBalanceAcc = await program.account.userBalance.fetch(makerBalanceAccount);
+      expect(makerBalanceAcc.quoteAmount.toNumber()).to.equal(0);
+      expect(makerBalanceAcc.quoteLocked.toNumber()).to.equal(FEE_QUOTE_AMOUNT);
+    })
+
+    it("Should decrement both the self trading orders and cancel the taker order", async() => {
+
+      const selfTradeMarket = await createFeeMarket(0, 0);
+      const makerBalanceAccount = getBalanceAccount(keypair.publicKey, 0, selfTradeMarket.market.publicKey);
+
+      const makerBaseLots = 3;
+      const takerBaseLots = 1;
+
+      const transferredBaseAmount = await placeSelfTrade(selfTradeMarket, {decrementAndCancel:{}}, makerBaseLots, takerBaseLots);
+
+      // the maker bid is decremented by the whole ask, which is neither matched nor escrowed
+      const bidsAcc = await program.account.bookSide.fetch(selfTradeMarket.bids.publicKey);
+      const asksAcc = await program.account.bookSide.fetch(selfTradeMarket.asks.publicKey);
+      expect(bidsAcc.orderCount.toNumber()).to.equal(1);
+      expect(bidsAcc.orders[0].baseAmount.toNumber()).to.equal((makerBaseLots - takerBaseLots) * 1000);
+      expect(asksAcc.orderCount.toNumber()).to.equal(0);
+      expect(transferredBaseAmount).to.equal(BigInt(0));
+
+      // the maker gets back the escrow of the decremented amount through an out event
+      const marketEventAcc = await program.account.marketEvents.fetch(selfTradeMarket.marketEvents.publicKey);
+      expect(marketEventAcc.count.toNumber()).to.equal(1);
+
+      const outEvent = getEvent(marketEventAcc, 0);
+      expect(outEvent.eventType.toNumber()).to.be.equal(1);
+      expect(outEvent.side.toNumber()).to.be.equal(0);
+      expect(outEvent.orderId.toNumber()).to.be.equal(bidsAcc.orders[0].orderId.toNumber());
+      expect(outEvent.baseAmount.toNumber()).to.be.equal(takerBaseLots * 1000);
+      expect(outEvent.quoteAmount.toNumber()).to.be.equal(FEE_PRICE * takerBaseLots);
+
+      await consumeFeeMarketEvents(selfTradeMarket);
+
+      const makerBalanceAcc = await program.account.userBalance.fetch(makerBalanceAccount);
+      expect(makerBalanceAcc.quoteAmount.toNumber()).to.equal(FEE_PRICE * takerBaseLots);
+      expect(makerBalanceAcc.quoteLocked.toNumber()).to.equal(FEE_PRICE * (makerBaseLots - takerBaseLots));
+    })
+
+    it("Should place a batch of orders above an expired crossing order", async() => {
+
+      const batchMarket = await createFeeMarket(0, 0);
+
+      const currentSlot = await connection.getSlot("confirmed");
+      await placeFeeMarketOrder(batchMarket, "ask", 0, null, {slot:{slot: new anchor.BN(currentSlot + 3)}});
+
+      // wait for the order to expire
+      while (await connection.getSlot("confirmed") < currentSlot + 3) {
+        await new Promise((resolve) => setTimeout(resolve, 400));
+      }
+
+      // the bid crosses the expired ask, but not the live orders
+      await program.methods
+      .placeOrders({
+        orders: [{
+          side: {bid:{}},
+          baseLots: FEE_BASE_LOTS,
+          quoteAmount: new anchor.BN(FEE_PRICE + 1000),
+          expiry: null,
+          clientOrderId: new anchor.BN(0),
+        }],
+        subAccount: 0,
+      })
+      .accounts({
+        user: keypair.publicKey.toBase58(),
+        market: batchMarket.market.publicKey.toBase58(),
+        bids: batchMarket.bids.publicKey.toBase58(),
+        asks: batchMarket.asks.publicKey.toBase58(),
+        userBaseTokenAccount: userBaseTokenAccount.toBase58(),
+        userQuoteTokenAccount: userQuoteTokenAccount.toBase58(),
+        baseTokenVault: batchMarket.baseTokenVault.toBase58(),
+        quoteTokenVault: batchMarket.quoteTokenVault.toBase58(),
+        baseToken: baseToken.publicKey.toBase58(),
+        quoteToken: quoteToken.publicKey.toBase58(),
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .rpc({commitment: "confirmed"});
+
+      const bidsAcc = await program.account.bookSide.fetch(batchMarket.bids.publicKey);
+      expect(bidsAcc.orderCount.toNumber()).to.equal(1);
+      expect(bidsAcc.orders[0].priceInTicks.toNumber()).to.equal((FEE_PRICE + 1000) / TICK_VALUE);
+    })
+  })
+});